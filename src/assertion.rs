@@ -0,0 +1,168 @@
+use crate::common::PropertyMap;
+use crate::condition;
+use crate::extractor::jwt;
+use crate::response::Response;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/*
+    Checks made against a response once it's been extracted from. Either a
+    condition, e.g. `status: == 200`, or a JWT to verify:
+    `{ "jwt": "json:access_token", "secret": "{jwt_secret}" }`.
+    A `public_key` can be given instead of a secret, as PEM or `file:<path>`.
+
+    Only a JWT check's `secret` and `public_key` have properties replaced. A
+    condition reads `{name}` as an operand of its own, keeping the property's
+    type, so `json:id == {expected_id}` works but `"id-{expected_id}"` is compared as written.
+*/
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Assertion {
+    Condition(String),
+    Jwt {
+        jwt: String,
+        #[serde(default)]
+        secret: Option<String>,
+        #[serde(default)]
+        public_key: Option<String>,
+        #[serde(default = "Assertion::default_expiry")]
+        check_expiry: bool,
+        #[serde(default)]
+        leeway_secs: u64,
+    },
+}
+
+impl Assertion {
+    fn default_expiry() -> bool {
+        true
+    }
+
+    // Replaces text in the JWT check's keys, leaving conditions for the resolver to read
+    pub fn map_text(&self, f: impl Fn(&str) -> String) -> Assertion {
+        match self {
+            Assertion::Condition(condition) => Assertion::Condition(condition.clone()),
+            Assertion::Jwt {
+                jwt,
+                secret,
+                public_key,
+                check_expiry,
+                leeway_secs,
+            } => Assertion::Jwt {
+                jwt: jwt.clone(),
+                secret: secret.as_deref().map(&f),
+                public_key: public_key.as_deref().map(&f),
+                check_expiry: *check_expiry,
+                leeway_secs: *leeway_secs,
+            },
+        }
+    }
+
+    // Swaps a `file:` public key for the file's contents
+    pub fn load_files(&mut self, working_directory: &Path) -> Result<()> {
+        if let Assertion::Jwt {
+            public_key: Some(key),
+            ..
+        } = self
+        {
+            if let Some(file_path) = key.strip_prefix("file:") {
+                let file_path = working_directory.join(file_path);
+                *key = fs::read_to_string(&file_path)
+                    .with_context(|| format!("Failed to load {}", file_path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Assertion::Condition(condition) => condition.clone(),
+            Assertion::Jwt { jwt, .. } => format!("JWT from {} verifies", jwt),
+        }
+    }
+
+    pub fn check(&self, response: &Response, properties: &PropertyMap) -> Result<()> {
+        match self {
+            Assertion::Condition(expression) => {
+                match condition::evaluate(expression, &(response, properties))? {
+                    true => Ok(()),
+                    false => Err(anyhow!("Condition was false")),
+                }
+            }
+            Assertion::Jwt {
+                jwt: source,
+                secret,
+                public_key,
+                check_expiry,
+                leeway_secs,
+            } => {
+                let token = jwt::token(source, response, properties)?;
+                jwt::verify(
+                    &token,
+                    secret.as_deref(),
+                    public_key.as_deref(),
+                    *check_expiry,
+                    *leeway_secs,
+                )
+                .map(|_| ())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Assertion;
+    use crate::common::{self, PropertyMap};
+    use crate::extractor::jwt::test::token;
+    use crate::response::ResponseBuilder;
+    use serde_json::json;
+
+    #[test]
+    fn conditions_and_jwts() {
+        let assertions: Vec<Assertion> = serde_json::from_str(
+            r#"[
+                "status: == 200 && jwt:json:token#role == \"admin\"",
+                { "jwt": "json:token", "secret": "{secret}" },
+                { "jwt": "json:token", "secret": "wrong" },
+                "status: == 201"
+            ]"#,
+        )
+        .unwrap();
+
+        let jwt = token(json!({ "role": "admin", "exp": common::now() + 60 }), "shh");
+        let body = json!({ "token": jwt }).to_string();
+        let response = ResponseBuilder::new()
+            .status(200)
+            .body(body.into_bytes())
+            .build();
+        let properties = PropertyMap::new();
+
+        let results: Vec<bool> = assertions
+            .iter()
+            .map(|assertion| assertion.map_text(|text| text.replace("{secret}", "shh")))
+            .map(|assertion| assertion.check(&response, &properties).is_ok())
+            .collect();
+        assert_eq!(results, [true, true, false, false]);
+    }
+
+    #[test]
+    fn conditions_read_properties_themselves() {
+        let response = ResponseBuilder::new()
+            .body(br#"{ "id": 5, "name": "id-{expected_id}" }"#.to_vec())
+            .build();
+        let mut properties = PropertyMap::new();
+        properties.insert("expected_id".to_owned(), json!(5));
+        let check = |condition: &str| {
+            Assertion::Condition(condition.to_owned())
+                .map_text(|text| text.replace("{expected_id}", "5"))
+                .check(&response, &properties)
+                .is_ok()
+        };
+
+        assert!(check("json:id == {expected_id}"));
+        assert!(check(r#"json:name == "id-{expected_id}""#));
+        assert!(!check(r#"json:name == "id-5""#));
+    }
+}
@@ -0,0 +1,471 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::{HeaderMap, Method, Uri};
+use serde::Deserialize;
+use url::Url;
+
+pub mod oauth2;
+
+pub use oauth2::OAuth2;
+
+/*
+  Authentication for a request, set with an `auth` block such as
+  `{ "type": "bearer", "token": "{token}" }`. A collection's `auth` is used by
+  any of its requests that don't have their own; `{ "type": "none" }` opts out.
+*/
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiKeyLocation {
+    #[default]
+    Header,
+    Query,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Auth {
+    None,
+    Basic {
+        username: String,
+        password: String,
+    },
+    Bearer {
+        token: String,
+    },
+    ApiKey {
+        key: String,
+        value: String,
+        #[serde(default, rename = "in")]
+        location: ApiKeyLocation,
+    },
+    Digest {
+        username: String,
+        password: String,
+    },
+    #[serde(rename = "oauth2")]
+    OAuth2(OAuth2),
+}
+
+impl Auth {
+    // Run every user supplied string through `f`, used for property replacement
+    pub fn map_text(&self, f: impl Fn(&str) -> String) -> Auth {
+        match self {
+            Auth::None => Auth::None,
+            Auth::Basic { username, password } => Auth::Basic {
+                username: f(username),
+                password: f(password),
+            },
+            Auth::Bearer { token } => Auth::Bearer { token: f(token) },
+            Auth::ApiKey {
+                key,
+                value,
+                location,
+            } => Auth::ApiKey {
+                key: f(key),
+                value: f(value),
+                location: *location,
+            },
+            Auth::Digest { username, password } => Auth::Digest {
+                username: f(username),
+                password: f(password),
+            },
+            Auth::OAuth2(config) => Auth::OAuth2(config.map_text(f)),
+        }
+    }
+
+    // Whether the credentials go in the Authorization header
+    pub fn sets_authorization(&self) -> bool {
+        match self {
+            Auth::None => false,
+            Auth::ApiKey { key, location, .. } => {
+                *location == ApiKeyLocation::Header && key.eq_ignore_ascii_case("authorization")
+            }
+            Auth::Basic { .. } | Auth::Bearer { .. } | Auth::Digest { .. } | Auth::OAuth2(_) => {
+                true
+            }
+        }
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value).with_context(|| "Invalid characters in auth value")
+}
+
+fn add_query(uri: &Uri, key: &str, value: &str) -> Result<Uri> {
+    let mut url =
+        Url::parse(&uri.to_string()).with_context(|| format!("Failed to parse [{}]", uri))?;
+    url.query_pairs_mut().append_pair(key, value);
+    url.as_str()
+        .parse::<Uri>()
+        .with_context(|| format!("Failed to add API key to [{}]", url))
+}
+
+/*
+    Adds the credentials to the outgoing request. Digest auth needs a challenge
+    from the server first, so is handled by `digest_authorization` instead.
+    OAuth2 needs its token fetching first, after which it's sent as a bearer token.
+*/
+pub fn apply(auth: &Auth, uri: &mut Uri, headers: &mut HeaderMap) -> Result<()> {
+    match auth {
+        Auth::None | Auth::Digest { .. } => {}
+        Auth::OAuth2(_) => return Err(anyhow!("OAuth2 token hasn't been fetched")),
+        Auth::Basic { username, password } => {
+            let encoded = STANDARD.encode(format!("{}:{}", username, password));
+            headers.insert(
+                header::AUTHORIZATION,
+                header_value(&format!("Basic {}", encoded))?,
+            );
+        }
+        Auth::Bearer { token } => {
+            headers.insert(
+                header::AUTHORIZATION,
+                header_value(&format!("Bearer {}", token))?,
+            );
+        }
+        Auth::ApiKey {
+            key,
+            value,
+            location: ApiKeyLocation::Header,
+        } => {
+            let name = HeaderName::from_bytes(key.as_bytes())
+                .with_context(|| format!("Invalid API key header name [{}]", key))?;
+            headers.insert(name, header_value(value)?);
+        }
+        Auth::ApiKey {
+            key,
+            value,
+            location: ApiKeyLocation::Query,
+        } => {
+            *uri = add_query(uri, key, value)?;
+        }
+    }
+    Ok(())
+}
+
+mod digest {
+    use anyhow::{anyhow, Result};
+    use md5::Md5;
+    use sha2::{Digest, Sha256};
+    use std::collections::HashMap;
+
+    // Splits `Digest realm="a", qop="auth,auth-int", nonce=abc` into its parameters
+    pub fn parse_challenge(challenge: &str) -> Result<HashMap<String, String>> {
+        // Auth schemes are case-insensitive
+        let trimmed = challenge.trim();
+        let params = match trimmed.get(..7) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("digest ") => &trimmed[7..],
+            _ => return Err(anyhow!("Not a Digest challenge [{}]", challenge)),
+        };
+
+        let mut map = HashMap::new();
+        let mut rest = params.trim();
+        while !rest.is_empty() {
+            let (key, after) = rest
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Malformed Digest challenge [{}]", challenge))?;
+            let (value, after) = match after.strip_prefix('"') {
+                Some(quoted) => {
+                    let end = quoted
+                        .find('"')
+                        .ok_or_else(|| anyhow!("Unterminated quote in [{}]", challenge))?;
+                    (&quoted[..end], &quoted[end + 1..])
+                }
+                None => match after.find(',') {
+                    Some(end) => (&after[..end], &after[end..]),
+                    None => (after, ""),
+                },
+            };
+            map.insert(key.trim().to_lowercase(), value.to_owned());
+            rest = after.trim_start().trim_start_matches(',').trim_start();
+        }
+        Ok(map)
+    }
+
+    pub fn hash(algorithm: &str, data: &str) -> Result<String> {
+        match algorithm.to_uppercase().trim_end_matches("-SESS") {
+            "MD5" => Ok(hex::encode(Md5::digest(data.as_bytes()))),
+            "SHA-256" => Ok(hex::encode(Sha256::digest(data.as_bytes()))),
+            other => Err(anyhow!("Unsupported Digest algorithm [{}]", other)),
+        }
+    }
+
+    pub struct Credentials<'a> {
+        pub username: &'a str,
+        pub password: &'a str,
+        pub method: &'a str,
+        pub uri: &'a str,
+        pub cnonce: &'a str,
+        pub nc: u32,
+    }
+
+    pub fn authorization(
+        challenge: &HashMap<String, String>,
+        credentials: &Credentials,
+    ) -> Result<String> {
+        let get = |key: &str| challenge.get(key).map(|value| value.as_str());
+        let realm = get("realm").unwrap_or_default();
+        let nonce = get("nonce").ok_or_else(|| anyhow!("Digest challenge has no nonce"))?;
+        let algorithm = get("algorithm").unwrap_or("MD5");
+        // Only `auth` is supported, as `auth-int` needs to hash the body
+        let qop = get("qop").map(|qop| qop.split(',').map(str::trim).any(|q| q == "auth"));
+        if qop == Some(false) {
+            return Err(anyhow!("Digest challenge doesn't allow qop=auth"));
+        }
+        let nc = format!("{:08x}", credentials.nc);
+
+        let mut ha1 = hash(
+            algorithm,
+            &format!(
+                "{}:{}:{}",
+                credentials.username, realm, credentials.password
+            ),
+        )?;
+        if algorithm.to_uppercase().ends_with("-SESS") {
+            ha1 = hash(
+                algorithm,
+                &format!("{}:{}:{}", ha1, nonce, credentials.cnonce),
+            )?;
+        }
+        let ha2 = hash(
+            algorithm,
+            &format!("{}:{}", credentials.method, credentials.uri),
+        )?;
+        let response = match qop {
+            Some(_) => hash(
+                algorithm,
+                &format!(
+                    "{}:{}:{}:{}:auth:{}",
+                    ha1, nonce, nc, credentials.cnonce, ha2
+                ),
+            )?,
+            None => hash(algorithm, &format!("{}:{}:{}", ha1, nonce, ha2))?,
+        };
+
+        let mut header = format!(
+            r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", algorithm={}, response="{}""#,
+            credentials.username, realm, nonce, credentials.uri, algorithm, response
+        );
+        if qop.is_some() {
+            header.push_str(&format!(
+                r#", qop=auth, nc={}, cnonce="{}""#,
+                nc, credentials.cnonce
+            ));
+        }
+        if let Some(opaque) = get("opaque") {
+            header.push_str(&format!(r#", opaque="{}""#, opaque));
+        }
+        Ok(header)
+    }
+}
+
+// The Authorization header answering a `WWW-Authenticate: Digest ...` challenge
+pub fn digest_authorization(
+    auth: &Auth,
+    challenge: &str,
+    method: &Method,
+    uri: &Uri,
+) -> Result<HeaderValue> {
+    let (username, password) = match auth {
+        Auth::Digest { username, password } => (username, password),
+        _ => return Err(anyhow!("Request doesn't use Digest auth")),
+    };
+
+    let challenge = digest::parse_challenge(challenge)?;
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    let cnonce = hex::encode(rand::random::<[u8; 16]>());
+
+    let value = digest::authorization(
+        &challenge,
+        &digest::Credentials {
+            username,
+            password,
+            method: method.as_str(),
+            uri: path,
+            cnonce: &cnonce,
+            nc: 1,
+        },
+    )?;
+    header_value(&value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{digest, ApiKeyLocation, Auth};
+    use hyper::header::{self, HeaderMap};
+    use hyper::{Method, Uri};
+
+    fn apply(auth: &Auth) -> (Uri, HeaderMap) {
+        let mut uri: Uri = "http://example.com/path?a=1".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        let result = super::apply(auth, &mut uri, &mut headers);
+        assert!(result.is_ok(), "Applying failed: {:?}", result.unwrap_err());
+        (uri, headers)
+    }
+
+    #[test]
+    fn basic() {
+        let (_, headers) = apply(&Auth::Basic {
+            username: "Aladdin".to_owned(),
+            password: "open sesame".to_owned(),
+        });
+        assert_eq!(
+            headers.get(header::AUTHORIZATION).unwrap(),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+
+    #[test]
+    fn bearer() {
+        let (_, headers) = apply(&Auth::Bearer {
+            token: "abc.def".to_owned(),
+        });
+        assert_eq!(
+            headers.get(header::AUTHORIZATION).unwrap(),
+            "Bearer abc.def"
+        );
+    }
+
+    #[test]
+    fn api_key_header() {
+        let (uri, headers) = apply(&Auth::ApiKey {
+            key: "X-Api-Key".to_owned(),
+            value: "secret".to_owned(),
+            location: ApiKeyLocation::Header,
+        });
+        assert_eq!(headers.get("x-api-key").unwrap(), "secret");
+        assert_eq!(uri, "http://example.com/path?a=1");
+    }
+
+    #[test]
+    fn api_key_query() {
+        let (uri, headers) = apply(&Auth::ApiKey {
+            key: "api key".to_owned(),
+            value: "a&b".to_owned(),
+            location: ApiKeyLocation::Query,
+        });
+        assert!(headers.is_empty());
+        assert_eq!(uri, "http://example.com/path?a=1&api+key=a%26b");
+    }
+
+    #[test]
+    fn deserialisation() {
+        let auth: Auth = serde_json::from_str(
+            r#"{ "type": "api_key", "key": "k", "value": "v", "in": "query" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            auth,
+            Auth::ApiKey {
+                key: "k".to_owned(),
+                value: "v".to_owned(),
+                location: ApiKeyLocation::Query
+            }
+        );
+        let auth: Auth = serde_json::from_str(r#"{ "type": "none" }"#).unwrap();
+        assert_eq!(auth, Auth::None);
+        assert!(serde_json::from_str::<Auth>(r#"{ "type": "bearer" }"#).is_err());
+    }
+
+    #[test]
+    fn map_text() {
+        let auth = Auth::Bearer {
+            token: "{token}".to_owned(),
+        };
+        assert_eq!(
+            auth.map_text(|text| text.replace("{token}", "abc")),
+            Auth::Bearer {
+                token: "abc".to_owned()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_challenge() {
+        let challenge = digest::parse_challenge(
+            r#"Digest realm="api@example.com", qop="auth,auth-int", nonce=abc123, opaque="xyz""#,
+        )
+        .unwrap();
+        assert_eq!(challenge["realm"], "api@example.com");
+        assert_eq!(challenge["qop"], "auth,auth-int");
+        assert_eq!(challenge["nonce"], "abc123");
+        assert_eq!(challenge["opaque"], "xyz");
+        assert!(digest::parse_challenge(r#"Basic realm="x""#).is_err());
+        assert!(digest::parse_challenge("Digest").is_err());
+
+        let challenge = digest::parse_challenge(r#"DIGEST realm="x", nonce=n"#).unwrap();
+        assert_eq!(challenge["nonce"], "n");
+    }
+
+    // Example from RFC 2617, section 3.5
+    #[test]
+    fn digest_rfc2617() {
+        let challenge = digest::parse_challenge(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        )
+        .unwrap();
+        let header = digest::authorization(
+            &challenge,
+            &digest::Credentials {
+                username: "Mufasa",
+                password: "Circle Of Life",
+                method: "GET",
+                uri: "/dir/index.html",
+                cnonce: "0a4f113b",
+                nc: 1,
+            },
+        )
+        .unwrap();
+        assert!(
+            header.contains(r#"response="6629fae49393a05397450978507c4ef1""#),
+            "{}",
+            header
+        );
+        assert!(header.contains("nc=00000001"));
+        assert!(header.contains(r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#));
+    }
+
+    // Example from RFC 7616, section 3.9.1
+    #[test]
+    fn digest_rfc7616_sha256() {
+        let challenge = digest::parse_challenge(
+            r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=SHA-256, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+        )
+        .unwrap();
+        let header = digest::authorization(
+            &challenge,
+            &digest::Credentials {
+                username: "Mufasa",
+                password: "Circle of Life",
+                method: "GET",
+                uri: "/dir/index.html",
+                cnonce: "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+                nc: 1,
+            },
+        )
+        .unwrap();
+        assert!(
+            header.contains(
+                r#"response="753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1""#
+            ),
+            "{}",
+            header
+        );
+    }
+
+    #[test]
+    fn digest_needs_digest_auth() {
+        let auth = Auth::Bearer {
+            token: "x".to_owned(),
+        };
+        let uri: Uri = "http://example.com/".parse().unwrap();
+        assert!(
+            super::digest_authorization(&auth, r#"Digest nonce="a""#, &Method::GET, &uri).is_err()
+        );
+    }
+}
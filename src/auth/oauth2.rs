@@ -0,0 +1,383 @@
+use crate::common::PropertyMap;
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::header::{self, HeaderValue};
+use hyper::{Body, Method, Request};
+use serde::Deserialize;
+use url::form_urlencoded;
+
+/*
+  Tokens are fetched from `token_url` the first time a request needs one and kept
+  in the run's properties as `<name>_access_token`, `<name>_expires_at` (unix
+  seconds) and `<name>_refresh_token`, so later requests reuse them and can refer
+  to them like any other property. A token that's about to expire is replaced
+  before use, with the refresh token if the server gave us one. Different
+  configs need different names, which is checked when the collections load.
+*/
+
+// Tokens this close to expiring are treated as expired already
+const EXPIRY_MARGIN_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GrantType {
+    #[default]
+    ClientCredentials,
+    Password,
+    RefreshToken,
+}
+
+// How the client id and secret are sent to the token endpoint
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientAuth {
+    #[default]
+    Basic,
+    Body,
+}
+
+fn default_name() -> String {
+    "oauth2".to_owned()
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct OAuth2 {
+    token_url: String,
+    #[serde(default)]
+    grant_type: GrantType,
+    client_id: String,
+    #[serde(default)]
+    client_secret: String,
+    #[serde(default)]
+    client_auth: ClientAuth,
+    #[serde(default)]
+    scope: Option<String>,
+    #[serde(default)]
+    username: String,
+    #[serde(default)]
+    password: String,
+    #[serde(default)]
+    refresh_token: String,
+    #[serde(default = "default_name")]
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    #[serde(default)]
+    expires_in: Option<u64>,
+    #[serde(default)]
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorResponse {
+    error: String,
+    #[serde(default)]
+    error_description: Option<String>,
+}
+
+impl OAuth2 {
+    pub fn map_text(&self, f: impl Fn(&str) -> String) -> OAuth2 {
+        OAuth2 {
+            token_url: f(&self.token_url),
+            grant_type: self.grant_type,
+            client_id: f(&self.client_id),
+            client_secret: f(&self.client_secret),
+            client_auth: self.client_auth,
+            scope: self.scope.as_deref().map(&f),
+            username: f(&self.username),
+            password: f(&self.password),
+            refresh_token: f(&self.refresh_token),
+            name: self.name.clone(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn key(&self, suffix: &str) -> String {
+        format!("{}_{}", self.name, suffix)
+    }
+
+    pub fn access_token<'a>(&self, cached_properties: &'a PropertyMap) -> Option<&'a str> {
+        cached_properties
+            .get(&self.key("access_token"))
+            .and_then(|value| value.as_str())
+    }
+
+    // The cached token, as long as it isn't about to expire
+    pub fn valid_token<'a>(&self, cached_properties: &'a PropertyMap, now: u64) -> Option<&'a str> {
+        let expires_at = cached_properties
+            .get(&self.key("expires_at"))
+            .and_then(|value| value.as_u64());
+        match expires_at {
+            Some(expires_at) if expires_at <= now + EXPIRY_MARGIN_SECS => None,
+            _ => self.access_token(cached_properties),
+        }
+    }
+
+    pub fn cached_refresh_token(&self, cached_properties: &PropertyMap) -> Option<String> {
+        cached_properties
+            .get(&self.key("refresh_token"))
+            .and_then(|value| value.as_str())
+            .map(|token| token.to_owned())
+    }
+
+    // Drops everything cached for this token, so the next fetch starts from scratch
+    pub fn forget(&self, cached_properties: &mut PropertyMap) {
+        for suffix in ["access_token", "expires_at", "refresh_token"] {
+            cached_properties.remove(&self.key(suffix));
+        }
+    }
+
+    fn form(&self, refresh_token: Option<&str>) -> String {
+        let mut form = form_urlencoded::Serializer::new(String::new());
+        match (refresh_token, self.grant_type) {
+            (Some(token), _) => {
+                form.append_pair("grant_type", "refresh_token");
+                form.append_pair("refresh_token", token);
+            }
+            (None, GrantType::ClientCredentials) => {
+                form.append_pair("grant_type", "client_credentials");
+            }
+            (None, GrantType::Password) => {
+                form.append_pair("grant_type", "password");
+                form.append_pair("username", &self.username);
+                form.append_pair("password", &self.password);
+            }
+            (None, GrantType::RefreshToken) => {
+                form.append_pair("grant_type", "refresh_token");
+                form.append_pair("refresh_token", &self.refresh_token);
+            }
+        }
+        if let Some(scope) = &self.scope {
+            form.append_pair("scope", scope);
+        }
+        if self.client_auth == ClientAuth::Body {
+            form.append_pair("client_id", &self.client_id);
+            if !self.client_secret.is_empty() {
+                form.append_pair("client_secret", &self.client_secret);
+            }
+        }
+        form.finish()
+    }
+
+    /*
+        The request to the token endpoint. With a refresh token that's the
+        refresh grant, otherwise the configured one.
+    */
+    pub fn token_request(&self, refresh_token: Option<&str>) -> Result<Request<Body>> {
+        let mut builder = Request::builder()
+            .method(Method::POST)
+            .uri(&self.token_url)
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(header::ACCEPT, "application/json");
+
+        if self.client_auth == ClientAuth::Basic {
+            // RFC 6749 wants both form encoded before they're joined
+            let encode =
+                |s: &str| form_urlencoded::byte_serialize(s.as_bytes()).collect::<String>();
+            let credentials = STANDARD.encode(format!(
+                "{}:{}",
+                encode(&self.client_id),
+                encode(&self.client_secret)
+            ));
+            builder = builder.header(
+                header::AUTHORIZATION,
+                HeaderValue::from_str(&format!("Basic {}", credentials))
+                    .with_context(|| "Invalid characters in OAuth2 client credentials")?,
+            );
+        }
+
+        builder
+            .body(Body::from(self.form(refresh_token)))
+            .with_context(|| format!("Failed to build token request for [{}]", self.token_url))
+    }
+
+    // Caches the token endpoint's answer, or turns its error into ours
+    pub fn store(
+        &self,
+        status: u16,
+        body: &[u8],
+        cached_properties: &mut PropertyMap,
+        now: u64,
+    ) -> Result<()> {
+        if !(200..300).contains(&status) {
+            return Err(match serde_json::from_slice::<ErrorResponse>(body) {
+                Ok(ErrorResponse {
+                    error,
+                    error_description: Some(description),
+                }) => anyhow!(
+                    "Token request failed with {}: {} ({})",
+                    status,
+                    error,
+                    description
+                ),
+                Ok(ErrorResponse { error, .. }) => {
+                    anyhow!("Token request failed with {}: {}", status, error)
+                }
+                Err(_) => anyhow!(
+                    "Token request failed with {}: {}",
+                    status,
+                    String::from_utf8_lossy(body)
+                ),
+            });
+        }
+
+        let token: TokenResponse = serde_json::from_slice(body)
+            .with_context(|| format!("Unexpected token response from [{}]", self.token_url))?;
+        cached_properties.insert(self.key("access_token"), token.access_token.into());
+        match token.expires_in {
+            Some(expires_in) => {
+                cached_properties.insert(
+                    self.key("expires_at"),
+                    now.saturating_add(expires_in).into(),
+                );
+            }
+            None => {
+                cached_properties.remove(&self.key("expires_at"));
+            }
+        }
+        // Servers that don't rotate refresh tokens leave the old one in use
+        if let Some(refresh_token) = token.refresh_token {
+            cached_properties.insert(self.key("refresh_token"), refresh_token.into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OAuth2;
+    use crate::common::PropertyMap;
+    use hyper::header;
+
+    fn config(extra: &str) -> OAuth2 {
+        let data = format!(
+            r#"{{ "token_url": "http://auth.example.com/token", "client_id": "my client", "client_secret": "s3cret" {} }}"#,
+            extra
+        );
+        serde_json::from_str(&data).unwrap()
+    }
+
+    async fn body(config: &OAuth2, refresh_token: Option<&str>) -> String {
+        let request = config.token_request(refresh_token).unwrap();
+        let bytes = hyper::body::to_bytes(request.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn grant_forms() {
+        let client = config("");
+        assert_eq!(body(&client, None).await, "grant_type=client_credentials");
+        let request = client.token_request(None).unwrap();
+        assert_eq!(
+            request.headers().get(header::AUTHORIZATION).unwrap(),
+            "Basic bXkrY2xpZW50OnMzY3JldA=="
+        );
+
+        let password = config(
+            r#", "grant_type": "password", "username": "ann", "password": "p&ss", "scope": "read write", "client_auth": "body""#,
+        );
+        assert_eq!(
+            body(&password, None).await,
+            "grant_type=password&username=ann&password=p%26ss&scope=read+write&client_id=my+client&client_secret=s3cret"
+        );
+        assert!(password
+            .token_request(None)
+            .unwrap()
+            .headers()
+            .get(header::AUTHORIZATION)
+            .is_none());
+
+        // A cached refresh token wins over the configured grant
+        assert_eq!(
+            body(&password, Some("r1")).await,
+            "grant_type=refresh_token&refresh_token=r1&scope=read+write&client_id=my+client&client_secret=s3cret"
+        );
+    }
+
+    #[test]
+    fn token_cached_until_expiry() {
+        let client = config(r#", "name": "api""#);
+        let mut props = PropertyMap::new();
+        client
+            .store(
+                200,
+                br#"{ "access_token": "t1", "token_type": "Bearer", "expires_in": 3600, "refresh_token": "r1" }"#,
+                &mut props,
+                1000,
+            )
+            .unwrap();
+        assert_eq!(props["api_access_token"], "t1");
+        assert_eq!(props["api_expires_at"], 4600);
+
+        assert_eq!(client.valid_token(&props, 1000), Some("t1"));
+        assert_eq!(client.valid_token(&props, 4580), None);
+        assert_eq!(client.cached_refresh_token(&props), Some("r1".to_owned()));
+
+        // No new refresh token keeps the old one, no expiry never expires
+        client
+            .store(200, br#"{ "access_token": "t2" }"#, &mut props, 5000)
+            .unwrap();
+        assert_eq!(client.valid_token(&props, u64::MAX / 2), Some("t2"));
+        assert_eq!(client.cached_refresh_token(&props), Some("r1".to_owned()));
+
+        client.forget(&mut props);
+        assert!(props.is_empty());
+    }
+
+    #[test]
+    fn named_tokens_kept_apart() {
+        let orders = config(r#", "scope": "orders", "name": "orders""#);
+        let users = config(r#", "scope": "users", "name": "users""#);
+        let mut props = PropertyMap::new();
+        orders
+            .store(200, br#"{ "access_token": "o1" }"#, &mut props, 0)
+            .unwrap();
+        users
+            .store(200, br#"{ "access_token": "u1" }"#, &mut props, 0)
+            .unwrap();
+        assert_eq!(orders.valid_token(&props, 0), Some("o1"));
+        assert_eq!(users.valid_token(&props, 0), Some("u1"));
+
+        users.forget(&mut props);
+        assert_eq!(orders.valid_token(&props, 0), Some("o1"));
+    }
+
+    #[test]
+    fn huge_expiry() {
+        let client = config("");
+        let mut props = PropertyMap::new();
+        let body = format!(r#"{{ "access_token": "t1", "expires_in": {} }}"#, u64::MAX);
+        client
+            .store(200, body.as_bytes(), &mut props, 1000)
+            .unwrap();
+        assert_eq!(props["oauth2_expires_at"], u64::MAX);
+        assert_eq!(client.valid_token(&props, 1000), Some("t1"));
+    }
+
+    #[test]
+    fn token_errors() {
+        let client = config("");
+        let mut props = PropertyMap::new();
+        let err = client
+            .store(
+                400,
+                br#"{ "error": "invalid_client", "error_description": "Unknown client" }"#,
+                &mut props,
+                0,
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Token request failed with 400: invalid_client (Unknown client)"
+        );
+        assert!(client.store(200, b"not json", &mut props, 0).is_err());
+        assert!(props.is_empty());
+    }
+}
@@ -0,0 +1,133 @@
+use encoding_rs::{Encoding, WINDOWS_1252};
+
+/*
+  Turns a body into text the way a browser would: a byte order mark wins,
+  then the `charset` from the Content-Type, then UTF-8. Bodies that are none
+  of those, and don't claim to be text, are binary and give back `None`.
+*/
+
+fn charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type
+        .split(';')
+        .skip(1)
+        .filter_map(|param| param.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(value.trim().trim_matches('"').as_bytes()))
+}
+
+// Media types that are text even without a charset
+fn is_textual(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_lowercase();
+    media_type.starts_with("text/")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-www-form-urlencoded"
+        )
+}
+
+pub fn decode(body: &[u8], content_type: Option<&str>) -> Option<String> {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(body) {
+        let (text, _) = encoding.decode_without_bom_handling(&body[bom_length..]);
+        return Some(text.into_owned());
+    }
+
+    if let Some(encoding) = content_type.and_then(charset) {
+        // A UTF-16 label without a BOM is little endian, as in browsers
+        let (text, _) = encoding.decode_without_bom_handling(body);
+        return Some(text.into_owned());
+    }
+
+    if let Ok(text) = std::str::from_utf8(body) {
+        // Control characters other than whitespace mean this isn't really text
+        let binary = text.chars().any(|c| c.is_control() && !c.is_whitespace());
+        if !binary || content_type.map(is_textual).unwrap_or_default() {
+            return Some(text.to_owned());
+        }
+        return None;
+    }
+
+    // Text without a charset that isn't UTF-8 is most likely Latin-1
+    match content_type.map(is_textual).unwrap_or_default() {
+        true => Some(
+            WINDOWS_1252
+                .decode_without_bom_handling(body)
+                .0
+                .into_owned(),
+        ),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::decode;
+
+    fn utf16(text: &str, big_endian: bool) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|unit| match big_endian {
+                true => unit.to_be_bytes(),
+                false => unit.to_le_bytes(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn declared_charsets() {
+        assert_eq!(
+            decode(b"caf\xe9", Some("text/plain; charset=ISO-8859-1")),
+            Some("café".to_owned())
+        );
+        assert_eq!(
+            decode(
+                &utf16("{\"a\":1}", false),
+                Some("application/json; charset=\"utf-16le\"")
+            ),
+            Some("{\"a\":1}".to_owned())
+        );
+        assert_eq!(
+            decode(&utf16("hej", true), Some("text/plain;charset=UTF-16BE")),
+            Some("hej".to_owned())
+        );
+    }
+
+    #[test]
+    fn byte_order_marks() {
+        let mut body = vec![0xFE, 0xFF];
+        body.extend(utf16("på", true));
+        // The BOM wins over whatever the header claims
+        assert_eq!(
+            decode(&body, Some("text/plain; charset=iso-8859-1")),
+            Some("på".to_owned())
+        );
+        assert_eq!(decode(b"\xEF\xBB\xBFtext", None), Some("text".to_owned()));
+    }
+
+    #[test]
+    fn undeclared() {
+        assert_eq!(
+            decode("smörgås".as_bytes(), None),
+            Some("smörgås".to_owned())
+        );
+        // Latin-1 is assumed for text that isn't UTF-8
+        assert_eq!(
+            decode(b"sm\xf6rg\xe5s", Some("text/html")),
+            Some("smörgås".to_owned())
+        );
+        assert_eq!(decode(b"\x89PNG\r\n\x1a\n\x00\x00", None), None);
+        assert_eq!(
+            decode(b"\x00\x01\x02", Some("application/octet-stream")),
+            None
+        );
+        assert_eq!(decode(b"sm\xf6rg\xe5s", None), None);
+    }
+}
@@ -1,20 +1,136 @@
-use crate::common::StringMap;
+use crate::auth::{self, Auth, OAuth2};
+use crate::common::{self, PropertyMap};
+use crate::context::RunContext;
+use crate::cookies::ChainCookies;
 use crate::response;
+use crate::signing::{self, Signing};
 
-use anyhow::Result;
+use anyhow::{anyhow, Context, Result};
 
-use hyper::Client;
+use hyper::{Body, Client, HeaderMap, Method, StatusCode, Uri};
+use std::time::{Duration, Instant};
+use url::Url;
+
+mod connector;
+mod encoding;
+mod proxy;
+
+pub use proxy::ProxySettings;
 
 /*
   Abstracts away the underlying REST client implementation, as we
   don't really care about it. Just execute the request and hand back
   errors or the response.
 */
+
+#[derive(Debug, Clone)]
+pub struct ClientOptions {
+    pub http2: bool,
+    pub keep_alive: bool,
+    pub pool_max_idle_per_host: usize,
+    pub pool_idle_timeout: Duration,
+    pub decompress: bool,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            http2: false,
+            keep_alive: true,
+            pool_max_idle_per_host: 8,
+            pool_idle_timeout: Duration::from_secs(90),
+            decompress: true,
+        }
+    }
+}
+
+// A client, and so a connection pool, shared by every request using the same proxy settings
+#[derive(Clone)]
+pub struct HttpClient {
+    inner: Client<connector::Connector, Body>,
+    proxy: ProxySettings,
+    decompress: bool,
+}
+
+impl HttpClient {
+    pub fn new(options: &ClientOptions, proxy: ProxySettings) -> Self {
+        let max_idle = if options.keep_alive {
+            options.pool_max_idle_per_host
+        } else {
+            0
+        };
+        let inner = Client::builder()
+            .pool_max_idle_per_host(max_idle)
+            .pool_idle_timeout(options.pool_idle_timeout)
+            .build(connector::build(options.http2, proxy.clone()));
+
+        HttpClient {
+            inner,
+            proxy,
+            decompress: options.decompress,
+        }
+    }
+
+    // Requests forwarded through an HTTP proxy carry its credentials themselves
+    fn authorize_proxy(&self, req: &mut hyper::Request<Body>) -> Result<()> {
+        if let Some(auth) = self.proxy.forward_authorization(req.uri()) {
+            req.headers_mut().insert(
+                hyper::header::PROXY_AUTHORIZATION,
+                hyper::header::HeaderValue::from_str(&auth)
+                    .with_context(|| "Invalid proxy credentials")?,
+            );
+        }
+        Ok(())
+    }
+}
+
+// Everything needed to (re)build a hyper request, as bodies can't be cloned
+#[derive(Debug, Clone)]
+struct PreparedRequest {
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Vec<u8>,
+}
+
+impl PreparedRequest {
+    // A copy carrying the signature, so each hop is signed for its own URI
+    fn signed(&self, signing: &Signing) -> Result<PreparedRequest> {
+        let mut signed = self.clone();
+        signing::sign(
+            signing,
+            &signed.method,
+            &signed.uri,
+            &mut signed.headers,
+            &signed.body,
+            chrono::Utc::now(),
+        )?;
+        Ok(signed)
+    }
+
+    fn build(&self) -> Result<hyper::Request<Body>> {
+        let mut builder = hyper::Request::builder()
+            .method(self.method.clone())
+            .uri(self.uri.clone());
+
+        for (key, value) in self.headers.iter() {
+            builder = builder.header(key, value);
+        }
+
+        builder
+            .body(Body::from(self.body.clone()))
+            .with_context(|| format!("Failed to build request for {}", self.uri))
+    }
+}
+
 mod request_converter {
-    use crate::common::StringMap;
+    use super::{encoding, PreparedRequest};
+    use crate::auth::{self, Auth};
+    use crate::common::PropertyMap;
     use crate::request;
     use anyhow::{Context, Result};
-    use hyper::{Body, Request};
+    use hyper::header::{self, HeaderName, HeaderValue};
+    use hyper::{HeaderMap, Uri};
 
     fn convert_verb(verb: request::Verb) -> hyper::Method {
         match verb {
@@ -24,51 +140,1055 @@ mod request_converter {
         }
     }
 
-    pub fn convert(req: &request::Request, cached_properties: &StringMap) -> Result<Request<Body>> {
-        let mut builder = Request::builder()
-            .method(convert_verb(req.verb()))
-            .uri(req.replaced_uri(cached_properties));
+    // Also hands back the body as it was before any compression, for `request.` extractors
+    pub fn convert(
+        req: &request::Request,
+        cached_properties: &PropertyMap,
+        auth: Option<&Auth>,
+    ) -> Result<(PreparedRequest, Vec<u8>)> {
+        let uri = req.replaced_uri(cached_properties);
+        let mut uri = uri
+            .parse::<Uri>()
+            .with_context(|| format!("Failed to parse URI [{}]", uri))?;
 
+        let mut headers = HeaderMap::new();
         for (key, value) in req.replaced_headers(cached_properties).iter() {
-            builder = builder.header(key, value);
+            headers.insert(
+                HeaderName::from_bytes(key.as_bytes())
+                    .with_context(|| format!("Invalid header name [{}]", key))?,
+                HeaderValue::from_str(value)
+                    .with_context(|| format!("Invalid value for header [{}]", key))?,
+            );
         }
 
-        return builder
-            .body(Body::from(req.replaced_body(cached_properties).clone()))
-            .with_context(|| "Failed?"); // TODO: Better message
+        if let Some(auth) = auth {
+            auth::apply(auth, &mut uri, &mut headers)?;
+        }
+
+        let replaced_body = req.replaced_body(cached_properties);
+        let mut body = replaced_body.clone();
+        if let Some(compression) = req.compress() {
+            body = encoding::encode(compression, &replaced_body)
+                .with_context(|| "Failed to compress request body")?;
+            headers.insert(
+                header::CONTENT_ENCODING,
+                HeaderValue::from_static(compression.name()),
+            );
+        }
+
+        let prepared = PreparedRequest {
+            method: convert_verb(req.verb()),
+            uri,
+            headers,
+            body,
+        };
+        Ok((prepared, replaced_body))
     }
 }
 
 mod response_converter {
+    use super::{encoding, PreparedRequest};
+    use crate::common::StringMap;
     use crate::response;
+    use anyhow::Context;
     use anyhow::Result;
     use hyper::{Body, Response};
+    use std::time::Instant;
+
+    fn convert_headers(headers: &hyper::HeaderMap) -> StringMap {
+        let mut map = StringMap::new();
+        for (key, value) in headers.iter() {
+            let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+            map.entry(key.as_str().to_owned())
+                .and_modify(|existing: &mut String| {
+                    existing.push_str(", ");
+                    existing.push_str(&value);
+                })
+                .or_insert(value);
+        }
+        map
+    }
 
-    pub async fn convert(resp: Response<Body>) -> Result<response::Response> {
+    // The first hop as it went out, cookies and signature included, with its uncompressed body
+    pub fn convert_request(hop: &PreparedRequest, body: &[u8]) -> response::SentRequest {
+        response::SentRequest {
+            method: hop.method.to_string(),
+            uri: hop.uri.to_string(),
+            headers: convert_headers(&hop.headers),
+            body: body.to_vec(),
+        }
+    }
+
+    // Finishes off a builder that already has everything known before the body arrived
+    pub async fn convert(
+        resp: Response<Body>,
+        builder: response::ResponseBuilder,
+        mut timings: response::Timings,
+        started: Instant,
+        decompress: bool,
+    ) -> Result<response::Response> {
         let status_code = resp.status();
-        let buf = hyper::body::to_bytes(resp).await.expect("Something");
+        let mut headers = convert_headers(resp.headers());
+
+        let transfer_started = Instant::now();
+        let buf = hyper::body::to_bytes(resp)
+            .await
+            .with_context(|| "Failed to read response body")?;
+        let mut body = buf.to_vec();
+        timings.transfer = transfer_started.elapsed();
+
+        /*
+            Once decoded the encoding and length headers no longer describe the body.
+            A body that can't be decoded is kept as it arrived, headers and all,
+            as the status and headers are still worth having.
+        */
+        let decompress_started = Instant::now();
+        if let Some(content_encoding) = headers.get("content-encoding") {
+            if decompress && !body.is_empty() {
+                match encoding::decode(content_encoding, body.clone(), encoding::MAX_DECODED_SIZE) {
+                    Ok(decoded) => {
+                        body = decoded;
+                        headers.remove("content-encoding");
+                        headers.remove("content-length");
+                    }
+                    Err(e) => println!("Keeping the body as it arrived: {:#}", e),
+                }
+            }
+        }
+        timings.decompress = decompress_started.elapsed();
+        timings.total = started.elapsed();
 
-        let converted = response::ResponseBuilder::new()
+        let converted = builder
             .status(status_code.as_u16())
-            .body(buf.to_vec())
+            .headers(headers)
+            .body(body)
+            .timings(timings)
             .build();
 
         Ok(converted)
     }
 }
 
+/*
+  hyper doesn't follow redirects itself, so work out the next request in the
+  chain here. Follows the same rules as browsers for rewriting the method,
+  and drops credentials when the redirect leaves the original origin.
+*/
+mod redirect {
+    use super::PreparedRequest;
+    use anyhow::{anyhow, Context, Result};
+    use hyper::header::{self, HeaderName};
+    use hyper::{Method, StatusCode, Uri};
+    use url::Url;
+
+    const SENSITIVE_HEADERS: [HeaderName; 4] = [
+        header::AUTHORIZATION,
+        header::COOKIE,
+        header::PROXY_AUTHORIZATION,
+        header::WWW_AUTHENTICATE,
+    ];
+
+    const BODY_HEADERS: [HeaderName; 4] = [
+        header::CONTENT_TYPE,
+        header::CONTENT_LENGTH,
+        header::CONTENT_ENCODING,
+        header::TRANSFER_ENCODING,
+    ];
+
+    pub fn is_redirect(status: StatusCode) -> bool {
+        matches!(status.as_u16(), 301 | 302 | 303 | 307 | 308)
+    }
+
+    // Returns the method to use for the next hop, and whether the body is kept
+    pub fn next_method(status: StatusCode, method: &Method) -> (Method, bool) {
+        match status.as_u16() {
+            301 | 302 if method == Method::POST => (Method::GET, false),
+            303 if method != Method::HEAD => (Method::GET, false),
+            _ => (method.clone(), true),
+        }
+    }
+
+    pub fn same_origin(a: &Url, b: &Url) -> bool {
+        a.scheme() == b.scheme()
+            && a.host_str() == b.host_str()
+            && a.port_or_known_default() == b.port_or_known_default()
+    }
+
+    pub fn resolve_location(current: &Uri, location: &str) -> Result<Url> {
+        let base = Url::parse(&current.to_string())
+            .with_context(|| format!("Failed to parse [{}] as a URL", current))?;
+        base.join(location)
+            .with_context(|| format!("Failed to resolve redirect location [{}]", location))
+    }
+
+    pub fn follow(
+        current: &PreparedRequest,
+        status: StatusCode,
+        location: &str,
+    ) -> Result<PreparedRequest> {
+        let target = resolve_location(&current.uri, location)?;
+        let origin = Url::parse(&current.uri.to_string())?;
+        let (method, keep_body) = next_method(status, &current.method);
+
+        let mut next = current.clone();
+        next.method = method;
+        next.uri = target
+            .as_str()
+            .parse::<Uri>()
+            .map_err(|e| anyhow!("Invalid redirect target [{}]: {}", target, e))?;
+        next.headers.remove(header::HOST);
+
+        if !keep_body {
+            next.body.clear();
+            for name in BODY_HEADERS.iter() {
+                next.headers.remove(name);
+            }
+        }
+
+        if !same_origin(&origin, &target) {
+            for name in SENSITIVE_HEADERS.iter() {
+                next.headers.remove(name);
+            }
+        }
+
+        Ok(next)
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::super::PreparedRequest;
+        use hyper::header::{self, HeaderValue};
+        use hyper::{HeaderMap, Method, StatusCode};
+
+        fn prepared(method: Method) -> PreparedRequest {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer x"));
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+            PreparedRequest {
+                method,
+                uri: "http://a.example.com/start".parse().unwrap(),
+                headers,
+                body: "hello".as_bytes().to_vec(),
+            }
+        }
+
+        #[test]
+        fn method_rewriting() {
+            let cases = [
+                (301, Method::POST, Method::GET, false),
+                (302, Method::POST, Method::GET, false),
+                (302, Method::DELETE, Method::DELETE, true),
+                (303, Method::POST, Method::GET, false),
+                (303, Method::HEAD, Method::HEAD, true),
+                (307, Method::POST, Method::POST, true),
+                (308, Method::POST, Method::POST, true),
+            ];
+            for (status, method, expected, keep_body) in cases {
+                let status = StatusCode::from_u16(status).unwrap();
+                assert_eq!(
+                    super::next_method(status, &method),
+                    (expected, keep_body),
+                    "{} {}",
+                    status,
+                    method
+                );
+            }
+        }
+
+        #[test]
+        fn relative_location() {
+            let next = super::follow(&prepared(Method::GET), StatusCode::FOUND, "../other?x=1");
+            assert!(next.is_ok(), "Following failed: {:?}", next.unwrap_err());
+            assert_eq!(next.unwrap().uri, "http://a.example.com/other?x=1");
+        }
+
+        #[test]
+        fn same_origin_keeps_credentials() {
+            let next = super::follow(
+                &prepared(Method::POST),
+                StatusCode::TEMPORARY_REDIRECT,
+                "/next",
+            )
+            .unwrap();
+            assert_eq!(next.method, Method::POST);
+            assert_eq!(next.body, "hello".as_bytes().to_vec());
+            assert!(next.headers.contains_key(header::AUTHORIZATION));
+            assert!(next.headers.contains_key(header::CONTENT_TYPE));
+        }
+
+        #[test]
+        fn cross_origin_strips_credentials() {
+            let next = super::follow(
+                &prepared(Method::POST),
+                StatusCode::SEE_OTHER,
+                "https://b.example.com/done",
+            )
+            .unwrap();
+            assert_eq!(next.method, Method::GET);
+            assert!(next.body.is_empty());
+            assert!(!next.headers.contains_key(header::AUTHORIZATION));
+            assert!(!next.headers.contains_key(header::CONTENT_TYPE));
+        }
+
+        #[test]
+        fn port_change_is_cross_origin() {
+            let next = super::follow(
+                &prepared(Method::GET),
+                StatusCode::FOUND,
+                "http://a.example.com:8080/",
+            )
+            .unwrap();
+            assert!(!next.headers.contains_key(header::AUTHORIZATION));
+        }
+    }
+}
+
+fn as_url(uri: &Uri) -> Result<Url> {
+    Url::parse(&uri.to_string()).with_context(|| format!("Failed to parse [{}] as a URL", uri))
+}
+
+async fn fetch_token(
+    client: &HttpClient,
+    config: &OAuth2,
+    refresh_token: Option<&str>,
+    cached_properties: &mut PropertyMap,
+) -> Result<()> {
+    let mut request = config.token_request(refresh_token)?;
+    client.authorize_proxy(&mut request)?;
+    let uri = request.uri().clone();
+    let resp = client
+        .inner
+        .request(request)
+        .await
+        .with_context(|| format!("Token request to {} failed", uri))?;
+    let status = resp.status().as_u16();
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .with_context(|| format!("Failed to read token response from {}", uri))?;
+    config.store(status, &body, cached_properties, common::now())
+}
+
+/*
+    Makes sure a request using OAuth2 has an unexpired token in the run's
+    properties, fetching a new one if not. Refreshing is tried first when we
+    have a refresh token, falling back to the configured grant if that fails.
+*/
+pub async fn authorize(
+    context: &RunContext,
+    req: &crate::request::Request,
+    cached_properties: &mut PropertyMap,
+) -> Result<()> {
+    let config = match req.replaced_auth(cached_properties) {
+        Some(Auth::OAuth2(config)) => config,
+        _ => return Ok(()),
+    };
+    if config
+        .valid_token(cached_properties, common::now())
+        .is_some()
+    {
+        return Ok(());
+    }
+
+    let client = context.client(req.proxy());
+    if let Some(refresh_token) = config.cached_refresh_token(cached_properties) {
+        match fetch_token(&client, &config, Some(&refresh_token), cached_properties).await {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                println!(
+                    "Refreshing OAuth2 token failed, requesting a new one: {:#}",
+                    e
+                );
+                config.forget(cached_properties);
+            }
+        }
+    }
+    fetch_token(&client, &config, None, cached_properties).await
+}
+
 pub async fn execute(
+    context: &RunContext,
     req: &crate::request::Request,
-    cached_properties: &StringMap,
+    cached_properties: &PropertyMap,
 ) -> Result<response::Response> {
-    let converted = request_converter::convert(req, cached_properties)?;
+    let auth = match req.replaced_auth(cached_properties) {
+        Some(Auth::OAuth2(config)) => Some(Auth::Bearer {
+            token: config
+                .access_token(cached_properties)
+                .map(|token| token.to_owned())
+                .ok_or_else(|| anyhow!("No OAuth2 token, was `authorize` skipped?"))?,
+        }),
+        auth => auth,
+    };
+    let (mut prepared, replaced_body) =
+        request_converter::convert(req, cached_properties, auth.as_ref())?;
+    let signing = req.replaced_signing(cached_properties);
+    let mut redirects: Vec<response::Redirect> = Vec::new();
+    let mut chain_cookies = ChainCookies::new();
+    let mut timings = response::Timings::default();
+    let policy = req.redirects();
+    let client = context.client(req.proxy());
+    if client.decompress
+        && !prepared
+            .headers
+            .contains_key(hyper::header::ACCEPT_ENCODING)
+    {
+        prepared.headers.insert(
+            hyper::header::ACCEPT_ENCODING,
+            hyper::header::HeaderValue::from_static(encoding::ACCEPT_ENCODING),
+        );
+    }
+    let mut sent_request = response::SentRequest::default();
+    let started = Instant::now();
+    let mut digest_answered = false;
+
+    loop {
+        let url = as_url(&prepared.uri)?;
+        // Cookies go in before signing, as the signature can cover the Cookie header
+        let mut hop = prepared.clone();
+        context
+            .cookies()
+            .add_request_cookies(&url, &mut hop.headers)?;
+        if let Some(signing) = &signing {
+            hop = hop.signed(signing)?;
+        }
+        // Taken again after a digest challenge is answered, so it has the real Authorization
+        if redirects.is_empty() {
+            sent_request = response_converter::convert_request(&hop, &replaced_body);
+        }
+        let mut outgoing = hop.build()?;
+        client.authorize_proxy(&mut outgoing)?;
+
+        println!("Making request with: {:?}", outgoing);
+        let sent = Instant::now();
+        let (resp, connection) = connector::timed(client.inner.request(outgoing)).await;
+        let resp = resp.with_context(|| format!("Request to {} failed", prepared.uri))?;
+
+        timings.dns += connection.dns();
+        timings.connect += connection.connect();
+        timings.tls += connection.tls();
+        timings.ttfb += sent.elapsed().saturating_sub(connection.total());
+        if connection.is_new_connection() {
+            timings.new_connections += 1;
+        }
+
+        context
+            .cookies()
+            .store_response_cookies(&url, resp.headers());
+        chain_cookies.store(&url, resp.headers());
+
+        // Digest auth needs the server's challenge first, so answer it and retry this hop
+        if let Some(auth @ Auth::Digest { .. }) = &auth {
+            let challenge = resp
+                .headers()
+                .get_all(hyper::header::WWW_AUTHENTICATE)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .find(|value| value.to_lowercase().starts_with("digest "));
+            if let (StatusCode::UNAUTHORIZED, Some(challenge), false) =
+                (resp.status(), challenge, digest_answered)
+            {
+                let value =
+                    auth::digest_authorization(auth, challenge, &prepared.method, &prepared.uri)?;
+                prepared.headers.insert(hyper::header::AUTHORIZATION, value);
+                digest_answered = true;
+
+                let _ = hyper::body::to_bytes(resp.into_body()).await;
+                continue;
+            }
+        }
+
+        let location = resp
+            .headers()
+            .get(hyper::header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_owned());
+
+        let location = match location {
+            Some(location) if policy.follow() && redirect::is_redirect(resp.status()) => location,
+            _ => {
+                // Cookies set by this response win over ones already in the jar
+                let mut cookies = context.cookies().values(&url);
+                cookies.extend(chain_cookies.values());
+                let builder = response::ResponseBuilder::new()
+                    .url(url.to_string())
+                    .redirects(redirects)
+                    .cookies(cookies)
+                    .request(sent_request);
+                return response_converter::convert(
+                    resp,
+                    builder,
+                    timings,
+                    started,
+                    client.decompress,
+                )
+                .await;
+            }
+        };
+
+        if redirects.len() >= policy.max() {
+            return Err(anyhow!(
+                "Too many redirects, gave up after {} hops at {}",
+                redirects.len(),
+                prepared.uri
+            ));
+        }
+
+        let status = resp.status();
+        let next = redirect::follow(&prepared, status, &location)?;
+        redirects.push(response::Redirect {
+            status: status.as_u16(),
+            from: prepared.uri.to_string(),
+            to: next.uri.to_string(),
+        });
+        prepared = next;
+        digest_answered = false;
+
+        // Drain the redirect's body so its connection can go back into the pool
+        let _ = hyper::body::to_bytes(resp.into_body()).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::encoding::{self, encode};
+    use super::proxy::stand_in;
+    use super::{ClientOptions, ProxySettings};
+    use crate::common::PropertyMap;
+    use crate::context::RunContext;
+    use crate::cookies::CookieJar;
+    use crate::request::{Compression, Request};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Response, Server};
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, Mutex};
+
+    // Serves /a -> /b -> /done, echoing the method used for the final hop
+    async fn redirect_server() -> SocketAddr {
+        let make_svc = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|req: hyper::Request<Body>| async move {
+                let resp = match req.uri().path() {
+                    "/a" => Response::builder()
+                        .status(302)
+                        .header("Location", "/b")
+                        .body(Body::empty()),
+                    "/b" => Response::builder()
+                        .status(307)
+                        .header("Location", "done")
+                        .body(Body::empty()),
+                    "/loop" => Response::builder()
+                        .status(301)
+                        .header("Location", "/loop")
+                        .body(Body::empty()),
+                    "/login" => Response::builder()
+                        .status(303)
+                        .header("Set-Cookie", "session=abc; Path=/")
+                        .header("Location", "/whoami")
+                        .body(Body::empty()),
+                    "/digest" => match req.headers().get("Authorization") {
+                        Some(value) => {
+                            Response::builder().body(Body::from(value.to_str().unwrap().to_owned()))
+                        }
+                        None => Response::builder()
+                            .status(401)
+                            .header("WWW-Authenticate", "Basic realm=\"x\"")
+                            .header(
+                                "WWW-Authenticate",
+                                "Digest realm=\"test\", qop=\"auth\", nonce=\"n1\"",
+                            )
+                            .body(Body::empty()),
+                    },
+                    "/whoami" => {
+                        let cookie = req
+                            .headers()
+                            .get("Cookie")
+                            .map(|value| value.to_str().unwrap().to_owned())
+                            .unwrap_or_default();
+                        Response::builder().body(Body::from(cookie))
+                    }
+                    "/gzip" => {
+                        let accepts = req
+                            .headers()
+                            .get("Accept-Encoding")
+                            .map(|value| value.to_str().unwrap().contains("gzip"))
+                            .unwrap_or_default();
+                        let body = br#"{ "ok": true }"#;
+                        match accepts {
+                            true => Response::builder()
+                                .header("Content-Encoding", "gzip")
+                                .body(Body::from(encode(Compression::Gzip, body).unwrap())),
+                            false => Response::builder().body(Body::from(&body[..])),
+                        }
+                    }
+                    "/zstd" => Response::builder()
+                        .header("Content-Encoding", "zstd")
+                        .body(Body::from("raw")),
+                    "/upload" => {
+                        let content_encoding = req
+                            .headers()
+                            .get("Content-Encoding")
+                            .map(|value| value.to_str().unwrap().to_owned())
+                            .unwrap_or_default();
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        let body = encoding::decode(
+                            &content_encoding,
+                            body.to_vec(),
+                            encoding::MAX_DECODED_SIZE,
+                        )
+                        .unwrap();
+                        Response::builder().body(Body::from(body))
+                    }
+                    _ => Response::builder().body(Body::from(req.method().to_string())),
+                };
+                Ok::<_, Infallible>(resp.unwrap())
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    fn request(addr: SocketAddr, path: &str, extra: &str) -> Request {
+        let data = format!(
+            r#"{{ "uri": "http://{}{}", "verb": "POST", "body": "x" {} }}"#,
+            addr, path, extra
+        );
+        serde_json::from_str(&data).unwrap()
+    }
+
+    fn context() -> RunContext {
+        RunContext::new(
+            ClientOptions::default(),
+            ProxySettings::default(),
+            CookieJar::in_memory(),
+        )
+    }
+
+    #[tokio::test]
+    async fn follows_redirect_chain() {
+        let addr = redirect_server().await;
+        let resp = super::execute(&context(), &request(addr, "/a", ""), &PropertyMap::new()).await;
+        assert!(resp.is_ok(), "Request failed: {:?}", resp.unwrap_err());
+
+        let resp = resp.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.body(), &"GET".as_bytes().to_vec());
+        assert_eq!(resp.url(), &format!("http://{}/done", addr));
+        assert_eq!(resp.redirects().len(), 2);
+        assert_eq!(resp.redirects()[0].status, 302);
+        assert_eq!(resp.redirects()[1].to, format!("http://{}/done", addr));
+    }
+
+    #[tokio::test]
+    async fn redirects_disabled() {
+        let addr = redirect_server().await;
+        let req = request(addr, "/a", r#", "follow_redirects": false"#);
+        let resp = super::execute(&context(), &req, &PropertyMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 302);
+        assert!(resp.redirects().is_empty());
+    }
+
+    #[tokio::test]
+    async fn too_many_redirects() {
+        let addr = redirect_server().await;
+        let req = request(addr, "/loop", r#", "max_redirects": 3"#);
+        assert!(super::execute(&context(), &req, &PropertyMap::new())
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn cookies_shared_across_requests() {
+        let addr = redirect_server().await;
+        let context = context();
+
+        // The cookie is set on a redirect, so should be sent on the next hop
+        let resp = super::execute(&context, &request(addr, "/login", ""), &PropertyMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.body(), &"session=abc".as_bytes().to_vec());
+        assert_eq!(resp.cookies().get("session"), Some(&"abc".to_owned()));
+
+        let resp = super::execute(&context, &request(addr, "/whoami", ""), &PropertyMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.body(), &"session=abc".as_bytes().to_vec());
+    }
+
+    #[tokio::test]
+    async fn cookies_disabled() {
+        let addr = redirect_server().await;
+        let context = RunContext::new(
+            ClientOptions::default(),
+            ProxySettings::default(),
+            CookieJar::disabled(),
+        );
+
+        let resp = super::execute(&context, &request(addr, "/login", ""), &PropertyMap::new())
+            .await
+            .unwrap();
+        assert!(resp.body().is_empty());
+        // Still visible to extractors, even though it isn't sent anywhere
+        assert_eq!(resp.cookies().get("session"), Some(&"abc".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn connections_reused() {
+        let addr = redirect_server().await;
+        let context = context();
+
+        let resp = super::execute(&context, &request(addr, "/a", ""), &PropertyMap::new())
+            .await
+            .unwrap();
+        // Every hop goes to the same server, so should share one connection
+        assert_eq!(resp.timings().new_connections, 1);
+        assert!(resp.timings().total >= resp.timings().ttfb);
+
+        let resp = super::execute(&context, &request(addr, "/done", ""), &PropertyMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.timings().new_connections, 0);
+    }
+
+    #[tokio::test]
+    async fn keep_alive_disabled() {
+        let addr = redirect_server().await;
+        let options = ClientOptions {
+            keep_alive: false,
+            ..Default::default()
+        };
+        let context = RunContext::new(options, ProxySettings::default(), CookieJar::in_memory());
+
+        let resp = super::execute(&context, &request(addr, "/a", ""), &PropertyMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.timings().new_connections, 3);
+    }
+
+    #[tokio::test]
+    async fn forwarded_through_http_proxy() {
+        let addr = redirect_server().await;
+        let proxy = stand_in::http_proxy().await;
+        let context = RunContext::new(
+            ClientOptions::default(),
+            ProxySettings::single(&format!("http://user:pass@{}", proxy), vec![]).unwrap(),
+            CookieJar::in_memory(),
+        );
+
+        let resp = super::execute(&context, &request(addr, "/done", ""), &PropertyMap::new())
+            .await
+            .unwrap();
+        let body = String::from_utf8(resp.body().clone()).unwrap();
+        assert_eq!(
+            body,
+            format!("POST http://{}/done HTTP/1.1|Basic dXNlcjpwYXNz", addr)
+        );
+    }
+
+    #[tokio::test]
+    async fn no_proxy_bypasses_proxy() {
+        let addr = redirect_server().await;
+        let proxy = stand_in::http_proxy().await;
+        let settings = ProxySettings::single(&format!("http://{}", proxy), vec![])
+            .unwrap()
+            .with_no_proxy("localhost,127.0.0.1");
+        let context = RunContext::new(ClientOptions::default(), settings, CookieJar::in_memory());
+
+        let resp = super::execute(&context, &request(addr, "/done", ""), &PropertyMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.body(), &"POST".as_bytes().to_vec());
+    }
+
+    #[tokio::test]
+    async fn request_proxy_overrides_default() {
+        let addr = redirect_server().await;
+        let proxy = stand_in::socks5_proxy(Some(("user", "pass"))).await;
+        let context = context();
+
+        let extra = format!(r#", "proxy": "socks5://user:pass@{}""#, proxy);
+        let resp = super::execute(
+            &context,
+            &request(addr, "/done", &extra),
+            &PropertyMap::new(),
+        )
+        .await;
+        assert!(resp.is_ok(), "Request failed: {:?}", resp.unwrap_err());
+        assert_eq!(resp.unwrap().body(), &"POST".as_bytes().to_vec());
+
+        // A proxy that refuses the credentials shows the request really went through it
+        let extra = format!(r#", "proxy": "socks5://user:wrong@{}""#, proxy);
+        let resp = super::execute(
+            &context,
+            &request(addr, "/done", &extra),
+            &PropertyMap::new(),
+        )
+        .await;
+        assert!(resp.is_err());
+    }
+
+    #[tokio::test]
+    async fn digest_challenge_answered() {
+        let addr = redirect_server().await;
+        let extra = r#", "auth": { "type": "digest", "username": "user", "password": "{pass}" }"#;
+        let mut props = PropertyMap::new();
+        props.insert("pass".to_owned(), "secret".into());
+
+        let resp = super::execute(&context(), &request(addr, "/digest", extra), &props)
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        let body = String::from_utf8(resp.body().clone()).unwrap();
+        assert!(
+            body.starts_with(r#"Digest username="user", realm="test", nonce="n1", uri="/digest""#)
+        );
+        assert!(body.contains("qop=auth, nc=00000001"));
+        assert_eq!(resp.request().headers["authorization"], body);
+    }
+
+    #[tokio::test]
+    async fn basic_auth_sent() {
+        let addr = redirect_server().await;
+        let extra = r#", "auth": { "type": "basic", "username": "user", "password": "pass" }"#;
+
+        let resp = super::execute(
+            &context(),
+            &request(addr, "/digest", extra),
+            &PropertyMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.body(), &"Basic dXNlcjpwYXNz".as_bytes().to_vec());
+    }
+
+    #[tokio::test]
+    async fn signature_replaces_auth() {
+        let addr = redirect_server().await;
+        let extra = r#", "auth": { "type": "bearer", "token": "t" },
+            "sign": { "type": "aws_sigv4", "service": "execute-api", "region": "eu-west-1",
+                      "access_key": "{key}", "secret_key": "secret" }"#;
+        let mut props = PropertyMap::new();
+        props.insert("key".to_owned(), "AKID".into());
+
+        let resp = super::execute(&context(), &request(addr, "/digest", extra), &props)
+            .await
+            .unwrap();
+        let body = String::from_utf8(resp.body().clone()).unwrap();
+        assert!(body.starts_with("AWS4-HMAC-SHA256 Credential=AKID/"));
+        assert!(body.contains(
+            "/eu-west-1/execute-api/aws4_request, SignedHeaders=accept-encoding;host;x-amz-date,"
+        ));
+    }
+
+    #[tokio::test]
+    async fn cookies_added_before_signing() {
+        let addr = redirect_server().await;
+        let context = context();
+        super::execute(&context, &request(addr, "/login", ""), &PropertyMap::new())
+            .await
+            .unwrap();
+
+        let extra = r#", "sign": { "type": "aws_sigv4", "service": "execute-api", "region": "eu-west-1",
+                                   "access_key": "AKID", "secret_key": "secret" }"#;
+        let resp = super::execute(
+            &context,
+            &request(addr, "/digest", extra),
+            &PropertyMap::new(),
+        )
+        .await
+        .unwrap();
+        let body = String::from_utf8(resp.body().clone()).unwrap();
+        assert!(
+            body.contains("SignedHeaders=accept-encoding;cookie;host;x-amz-date,"),
+            "{}",
+            body
+        );
+        assert_eq!(resp.request().headers["cookie"], "session=abc");
+        assert_eq!(resp.request().headers["authorization"], body);
+    }
+
+    // Hands out tokens for the client credentials grant and the `r1` refresh token
+    async fn token_server(grants: Arc<Mutex<Vec<String>>>) -> SocketAddr {
+        let make_svc = make_service_fn(move |_| {
+            let grants = grants.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: hyper::Request<Body>| {
+                    let grants = grants.clone();
+                    async move {
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        let form = String::from_utf8(body.to_vec()).unwrap();
+                        grants.lock().unwrap().push(form.clone());
+                        let (status, token) = match form.as_str() {
+                            "grant_type=client_credentials" => (
+                                200,
+                                r#"{ "access_token": "cc", "expires_in": 10, "refresh_token": "r1" }"#,
+                            ),
+                            "grant_type=refresh_token&refresh_token=r1" => (
+                                200,
+                                r#"{ "access_token": "refreshed", "expires_in": 3600 }"#,
+                            ),
+                            _ => (400, r#"{ "error": "invalid_grant" }"#),
+                        };
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .body(Body::from(token))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_svc);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        addr
+    }
+
+    async fn authorized_send(
+        context: &RunContext,
+        request: &Request,
+        props: &mut PropertyMap,
+    ) -> String {
+        super::authorize(context, request, props).await.unwrap();
+        let resp = super::execute(context, request, props).await.unwrap();
+        String::from_utf8(resp.body().clone()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn oauth2_token_fetched_and_refreshed() {
+        let addr = redirect_server().await;
+        let grants = Arc::new(Mutex::new(Vec::new()));
+        let token_addr = token_server(grants.clone()).await;
+        let extra = format!(
+            r#", "auth": {{ "type": "oauth2", "token_url": "http://{}/token", "client_id": "id", "client_secret": "secret" }}"#,
+            token_addr
+        );
+        let request = request(addr, "/digest", &extra);
+        let context = context();
+        let mut props = PropertyMap::new();
+
+        assert_eq!(
+            authorized_send(&context, &request, &mut props).await,
+            "Bearer cc"
+        );
+        assert_eq!(props["oauth2_access_token"], "cc");
+
+        // The first token expires within the margin, so is refreshed, then reused
+        assert_eq!(
+            authorized_send(&context, &request, &mut props).await,
+            "Bearer refreshed"
+        );
+        assert_eq!(
+            authorized_send(&context, &request, &mut props).await,
+            "Bearer refreshed"
+        );
+        assert_eq!(grants.lock().unwrap().len(), 2);
+
+        // A refresh token the server rejects falls back to a new grant
+        props.insert("oauth2_expires_at".to_owned(), 0.into());
+        props.insert("oauth2_refresh_token".to_owned(), "stale".into());
+        assert_eq!(
+            authorized_send(&context, &request, &mut props).await,
+            "Bearer cc"
+        );
+        assert_eq!(
+            grants.lock().unwrap()[2..],
+            [
+                "grant_type=refresh_token&refresh_token=stale".to_owned(),
+                "grant_type=client_credentials".to_owned()
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn oauth2_token_errors_reported() {
+        let addr = redirect_server().await;
+        let token_addr = token_server(Arc::new(Mutex::new(Vec::new()))).await;
+        let extra = format!(
+            r#", "auth": {{ "type": "oauth2", "grant_type": "refresh_token", "refresh_token": "bad", "token_url": "http://{}/token", "client_id": "id" }}"#,
+            token_addr
+        );
+        let mut props = PropertyMap::new();
+        let err = super::authorize(&context(), &request(addr, "/digest", &extra), &mut props)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Token request failed with 400: invalid_grant"
+        );
+    }
+
+    #[tokio::test]
+    async fn compressed_responses_decoded() {
+        let addr = redirect_server().await;
+        let resp = super::execute(&context(), &request(addr, "/gzip", ""), &PropertyMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.body(), &br#"{ "ok": true }"#.to_vec());
+        assert!(!resp.headers().contains_key("content-encoding"));
+
+        // With decompression off nothing asks for compression, so nothing comes back compressed
+        let options = ClientOptions {
+            decompress: false,
+            ..Default::default()
+        };
+        let context = RunContext::new(options, ProxySettings::default(), CookieJar::in_memory());
+        let extra = r#", "headers": { "Accept-Encoding": "gzip" }"#;
+        let resp = super::execute(
+            &context,
+            &request(addr, "/gzip", extra),
+            &PropertyMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.headers().get("content-encoding").unwrap(), "gzip");
+        assert_eq!(
+            encoding::decode("gzip", resp.body().clone(), encoding::MAX_DECODED_SIZE).unwrap(),
+            br#"{ "ok": true }"#.to_vec()
+        );
+    }
+
+    #[tokio::test]
+    async fn undecodable_body_kept() {
+        let addr = redirect_server().await;
+        let resp = super::execute(&context(), &request(addr, "/zstd", ""), &PropertyMap::new())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.body(), &b"raw".to_vec());
+        assert_eq!(resp.headers().get("content-encoding").unwrap(), "zstd");
+    }
 
-    println!("Making request with: {:?}", converted);
-    let client = Client::new();
-    let resp = client.request(converted).await;
+    #[tokio::test]
+    async fn request_body_compressed() {
+        let addr = redirect_server().await;
+        let extra = r#", "compress": "br""#;
+        let resp = super::execute(
+            &context(),
+            &request(addr, "/upload", extra),
+            &PropertyMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.body(), &b"x".to_vec());
+        // What was sent is kept uncompressed for `request.` extractors
+        assert_eq!(resp.request().body, b"x".to_vec());
+        assert_eq!(resp.request().method, "POST");
+        assert_eq!(resp.request().headers["content-encoding"], "br");
 
-    match resp {
-        Ok(result) => response_converter::convert(result).await,
-        Err(e) => panic!(""),
+        // A Content-Encoding set by hand isn't ours to decode, so is sent as it is
+        let extra = r#", "headers": { "Content-Encoding": "zstd" }"#;
+        let resp = super::execute(
+            &context(),
+            &request(addr, "/done", extra),
+            &PropertyMap::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(resp.request().body, b"x".to_vec());
     }
 }
@@ -0,0 +1,205 @@
+use super::proxy::{ProxyConnector, ProxySettings};
+use hyper::client::connect::dns::GaiResolver;
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/*
+  The connector stack used by the shared client. Each layer is wrapped in
+  `Timed`, which records how long that layer took to produce a connection:
+
+    Timed(Total) -> TLS -> Timed(Tcp) -> proxy -> HttpConnector -> Timed(Dns) -> resolver
+
+  so going through a proxy counts as part of connecting.
+
+  Timings are reported through a task-local set up by `timed`, so they land
+  on the request that caused the connection. Reused connections from the pool
+  never hit the connector, so record nothing.
+*/
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectTimings {
+    pub dns: Option<Duration>,
+    pub tcp: Option<Duration>,
+    pub total: Option<Duration>,
+}
+
+impl ConnectTimings {
+    pub fn dns(&self) -> Duration {
+        self.dns.unwrap_or_default()
+    }
+
+    // TCP connection time, without the DNS lookup
+    pub fn connect(&self) -> Duration {
+        self.tcp.unwrap_or_default().saturating_sub(self.dns())
+    }
+
+    // Anything the outer layers did on top of the TCP connection, i.e. the TLS handshake
+    pub fn tls(&self) -> Duration {
+        self.total
+            .unwrap_or_default()
+            .saturating_sub(self.tcp.unwrap_or_default())
+    }
+
+    pub fn total(&self) -> Duration {
+        self.total.unwrap_or_default()
+    }
+
+    pub fn is_new_connection(&self) -> bool {
+        self.total.is_some()
+    }
+}
+
+tokio::task_local! {
+    static TIMINGS: Arc<Mutex<ConnectTimings>>;
+}
+
+// Runs `future`, collecting timings for any connection it opens
+pub async fn timed<F: Future>(future: F) -> (F::Output, ConnectTimings) {
+    let timings = Arc::new(Mutex::new(ConnectTimings::default()));
+    let output = TIMINGS.scope(timings.clone(), future).await;
+    let timings = *timings.lock().unwrap();
+    (output, timings)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Stage {
+    Dns,
+    Tcp,
+    Total,
+}
+
+fn record(stage: Stage, elapsed: Duration) {
+    // Outside of `timed` (e.g. a connection finishing in the background) there's nowhere to record to
+    let _ = TIMINGS.try_with(|timings| {
+        let mut timings = timings.lock().unwrap();
+        match stage {
+            Stage::Dns => timings.dns = Some(elapsed),
+            Stage::Tcp => timings.tcp = Some(elapsed),
+            Stage::Total => timings.total = Some(elapsed),
+        }
+    });
+}
+
+#[derive(Debug, Clone)]
+pub struct Timed<S> {
+    inner: S,
+    stage: Stage,
+}
+
+impl<S> Timed<S> {
+    pub fn new(inner: S, stage: Stage) -> Self {
+        Timed { inner, stage }
+    }
+}
+
+impl<S, Req> Service<Req> for Timed<S>
+where
+    S: Service<Req>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let stage = self.stage;
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let started = Instant::now();
+            let result = future.await;
+            record(stage, started.elapsed());
+            result
+        })
+    }
+}
+
+pub type Connector =
+    Timed<HttpsConnector<Timed<ProxyConnector<HttpConnector<Timed<GaiResolver>>>>>>;
+
+pub fn build(http2: bool, proxy: ProxySettings) -> Connector {
+    let mut http = HttpConnector::new_with_resolver(Timed::new(GaiResolver::new(), Stage::Dns));
+    http.enforce_http(false);
+    let tcp = Timed::new(ProxyConnector::new(http, proxy), Stage::Tcp);
+
+    let builder = HttpsConnectorBuilder::new()
+        .with_webpki_roots()
+        .https_or_http()
+        .enable_http1();
+    let https = if http2 {
+        builder.enable_http2().wrap_connector(tcp)
+    } else {
+        builder.wrap_connector(tcp)
+    };
+
+    Timed::new(https, Stage::Total)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Stage, Timed};
+    use hyper::service::Service;
+    use std::convert::Infallible;
+    use std::time::Duration;
+
+    #[derive(Clone)]
+    struct Sleep(Duration);
+
+    impl Service<()> for Sleep {
+        type Response = ();
+        type Error = Infallible;
+        type Future =
+            std::pin::Pin<Box<dyn std::future::Future<Output = Result<(), Infallible>> + Send>>;
+
+        fn poll_ready(
+            &mut self,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), Infallible>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: ()) -> Self::Future {
+            let duration = self.0;
+            Box::pin(async move {
+                tokio::time::sleep(duration).await;
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn records_stages() {
+        let mut dns = Timed::new(Sleep(Duration::from_millis(5)), Stage::Dns);
+        let mut total = Timed::new(Sleep(Duration::from_millis(20)), Stage::Total);
+
+        let (_, timings) = super::timed(async {
+            dns.call(()).await.unwrap();
+            total.call(()).await.unwrap();
+        })
+        .await;
+
+        assert!(timings.is_new_connection());
+        assert!(timings.dns() >= Duration::from_millis(5));
+        assert_eq!(timings.connect(), Duration::ZERO);
+        assert!(timings.tls() >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn nothing_recorded_outside_scope() {
+        let mut dns = Timed::new(Sleep(Duration::from_millis(1)), Stage::Dns);
+        dns.call(()).await.unwrap();
+
+        let (_, timings) = super::timed(async {}).await;
+        assert!(!timings.is_new_connection());
+        assert!(timings.dns.is_none());
+    }
+}
@@ -0,0 +1,135 @@
+use crate::request::Compression;
+use anyhow::{anyhow, Context, Result};
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use std::io::{Read, Write};
+
+/*
+  Content-Encoding support. Responses are decoded before anything looks at
+  them, so extractors always see the real body, and request bodies can be
+  compressed for endpoints that accept compressed uploads.
+*/
+
+pub const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+// Largest body decompression can give, as a tiny compressed body can expand to fill memory
+pub const MAX_DECODED_SIZE: u64 = 256 * 1024 * 1024;
+
+fn read_all(reader: impl Read, max_size: u64) -> std::io::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    reader.take(max_size + 1).read_to_end(&mut decoded)?;
+    if decoded.len() as u64 > max_size {
+        return Err(std::io::Error::other(format!(
+            "Decompressed body is over {} bytes",
+            max_size
+        )));
+    }
+    Ok(decoded)
+}
+
+fn decode_one(encoding: &str, body: Vec<u8>, max_size: u64) -> Result<Vec<u8>> {
+    match encoding {
+        "identity" | "" => Ok(body),
+        "gzip" | "x-gzip" => read_all(GzDecoder::new(body.as_slice()), max_size)
+            .with_context(|| "Failed to decompress gzip body"),
+        // Meant to be zlib wrapped, but plenty of servers send raw deflate
+        "deflate" => read_all(ZlibDecoder::new(body.as_slice()), max_size)
+            .or_else(|_| read_all(DeflateDecoder::new(body.as_slice()), max_size))
+            .with_context(|| "Failed to decompress deflate body"),
+        "br" => read_all(brotli::Decompressor::new(body.as_slice(), 4096), max_size)
+            .with_context(|| "Failed to decompress brotli body"),
+        other => Err(anyhow!("Unsupported Content-Encoding [{}]", other)),
+    }
+}
+
+// Undoes a `Content-Encoding` header's value, last applied first, failing past `max_size`
+pub fn decode(content_encoding: &str, body: Vec<u8>, max_size: u64) -> Result<Vec<u8>> {
+    content_encoding
+        .split(',')
+        .map(|encoding| encoding.trim().to_lowercase())
+        .rev()
+        .try_fold(body, |body, encoding| decode_one(&encoding, body, max_size))
+}
+
+pub fn encode(compression: Compression, body: &[u8]) -> Result<Vec<u8>> {
+    let encoded = match compression {
+        Compression::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()?
+        }
+        Compression::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(body)?;
+            encoder.finish()?
+        }
+        Compression::Br => {
+            let mut encoded = Vec::new();
+            let mut encoder = brotli::CompressorWriter::new(&mut encoded, 4096, 5, 22);
+            encoder.write_all(body)?;
+            drop(encoder);
+            encoded
+        }
+    };
+    Ok(encoded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode, MAX_DECODED_SIZE};
+    use crate::request::Compression;
+    use flate2::write::DeflateEncoder;
+    use std::io::Write;
+
+    const BODY: &[u8] = br#"{ "name": "Mejlmannen", "tags": ["a", "b", "c"] }"#;
+
+    fn decode(content_encoding: &str, body: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        super::decode(content_encoding, body, MAX_DECODED_SIZE)
+    }
+
+    #[test]
+    fn round_trips() {
+        for (compression, name) in [
+            (Compression::Gzip, "gzip"),
+            (Compression::Deflate, "deflate"),
+            (Compression::Br, "br"),
+        ] {
+            let encoded = encode(compression, BODY).unwrap();
+            assert_ne!(encoded, BODY);
+            assert_eq!(decode(name, encoded).unwrap(), BODY, "{}", name);
+        }
+    }
+
+    #[test]
+    fn stacked_and_raw_deflate() {
+        let gzipped = encode(Compression::Gzip, BODY).unwrap();
+        let both = encode(Compression::Br, &gzipped).unwrap();
+        assert_eq!(decode("gzip, br", both).unwrap(), BODY);
+
+        let mut raw = DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        raw.write_all(BODY).unwrap();
+        assert_eq!(decode("deflate", raw.finish().unwrap()).unwrap(), BODY);
+
+        assert_eq!(decode("identity", BODY.to_vec()).unwrap(), BODY);
+        assert!(decode("compress", BODY.to_vec()).is_err());
+        assert!(decode("gzip", BODY.to_vec()).is_err());
+    }
+
+    #[test]
+    fn size_limited() {
+        let zeros = vec![0; 100_000];
+        for (compression, name) in [
+            (Compression::Gzip, "gzip"),
+            (Compression::Deflate, "deflate"),
+            (Compression::Br, "br"),
+        ] {
+            let encoded = encode(compression, &zeros).unwrap();
+            assert!(encoded.len() < 1000, "{}", name);
+            assert_eq!(
+                super::decode(name, encoded.clone(), 100_000).unwrap(),
+                zeros
+            );
+            assert!(super::decode(name, encoded, 99_999).is_err(), "{}", name);
+        }
+    }
+}
@@ -1 +1,33 @@
+use serde_json::Value;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 pub type StringMap = std::collections::HashMap<String, String>;
+
+// Properties keep the type they were given or extracted with, so arrays stay arrays
+pub type PropertyMap = std::collections::HashMap<String, Value>;
+
+// How a property reads when put into text. Strings go in as they are, anything else as JSON
+pub fn property_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+// The current time in unix seconds, as token expiry times are given
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+// An empty directory for one test, kept apart from other tests and other runs
+#[cfg(test)]
+pub fn scratch_dir(test: &str) -> std::path::PathBuf {
+    let dir =
+        std::path::PathBuf::from("target/tmp").join(format!("{}_{}", test, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,517 @@
+use crate::common::PropertyMap;
+use crate::extractor;
+use crate::response::Response;
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/*
+  Small boolean expression language used to decide things about a response,
+  e.g. `json:status == "done" && header:Retry-After == null`.
+
+  Operands are either literals (strings, numbers, true/false/null) or
+  extractor strings, which are resolved through a `Resolver`.
+*/
+
+pub trait Resolver {
+    fn extract(&self, extractor: &str) -> Result<Value>;
+}
+
+// `{name}` reads a property, e.g. `{status_of.login}`. Missing ones read as null
+fn property(operand: &str, properties: &PropertyMap) -> Option<Value> {
+    let name = operand.strip_prefix('{')?.strip_suffix('}')?;
+    Some(properties.get(name).cloned().unwrap_or(Value::Null))
+}
+
+// Properties alone, for deciding things before there's a response
+impl Resolver for PropertyMap {
+    fn extract(&self, operand: &str) -> Result<Value> {
+        property(operand, self)
+            .ok_or_else(|| anyhow!("Only {{property}} operands work here, not [{}]", operand))
+    }
+}
+
+// A response, along with the properties `{name}` and `jwt:property:` can read
+impl Resolver for (&Response, &PropertyMap) {
+    fn extract(&self, extractor: &str) -> Result<Value> {
+        match property(extractor, self.1) {
+            Some(value) => Ok(value),
+            None => extractor::extract_value(extractor, self.0, self.1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Compare(CompareOp),
+    Literal(Value),
+    Extract(String),
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Literal(Value),
+    Extract(String),
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare(Operand, CompareOp, Operand),
+    Truthy(Operand),
+}
+
+mod lexer {
+    use super::{CompareOp, Token};
+    use anyhow::{anyhow, Result};
+    use serde_json::Value;
+
+    fn read_string(chars: &[char], start: usize) -> Result<(String, usize)> {
+        let mut out = String::new();
+        let mut idx = start + 1;
+        while idx < chars.len() {
+            match chars[idx] {
+                '"' => return Ok((out, idx + 1)),
+                '\\' if idx + 1 < chars.len() => {
+                    out.push(chars[idx + 1]);
+                    idx += 2;
+                }
+                c => {
+                    out.push(c);
+                    idx += 1;
+                }
+            }
+        }
+        Err(anyhow!("Unterminated string starting at {}", start))
+    }
+
+    /*
+        Extractor strings can contain brackets, parentheses and quoted strings
+        (e.g. JSON filters) so only stop on whitespace or a closing parenthesis
+        that doesn't belong to the operand itself.
+    */
+    fn read_word(chars: &[char], start: usize) -> (String, usize) {
+        let mut depth = 0;
+        let mut in_quote: Option<char> = None;
+        let mut idx = start;
+        while idx < chars.len() {
+            let c = chars[idx];
+            if let Some(quote) = in_quote {
+                if c == quote {
+                    in_quote = None;
+                }
+            } else {
+                match c {
+                    '"' | '\'' => in_quote = Some(c),
+                    '(' | '[' => depth += 1,
+                    ')' | ']' if depth > 0 => depth -= 1,
+                    ')' => break,
+                    c if c.is_whitespace() && depth == 0 => break,
+                    _ => {}
+                }
+            }
+            idx += 1;
+        }
+        (chars[start..idx].iter().collect(), idx)
+    }
+
+    pub fn tokenize(text: &str) -> Result<Vec<Token>> {
+        let chars: Vec<char> = text.chars().collect();
+        let mut tokens = Vec::new();
+        let mut idx = 0;
+
+        while idx < chars.len() {
+            let c = chars[idx];
+            let next = chars.get(idx + 1).copied();
+            let (token, len) = match (c, next) {
+                (c, _) if c.is_whitespace() => {
+                    idx += 1;
+                    continue;
+                }
+                ('(', _) => (Token::LParen, 1),
+                (')', _) => (Token::RParen, 1),
+                ('&', Some('&')) => (Token::And, 2),
+                ('|', Some('|')) => (Token::Or, 2),
+                ('=', Some('=')) => (Token::Compare(CompareOp::Eq), 2),
+                ('!', Some('=')) => (Token::Compare(CompareOp::Ne), 2),
+                ('<', Some('=')) => (Token::Compare(CompareOp::Le), 2),
+                ('>', Some('=')) => (Token::Compare(CompareOp::Ge), 2),
+                ('<', _) => (Token::Compare(CompareOp::Lt), 1),
+                ('>', _) => (Token::Compare(CompareOp::Gt), 1),
+                ('!', _) => (Token::Not, 1),
+                ('"', _) => {
+                    let (value, end) = read_string(&chars, idx)?;
+                    tokens.push(Token::Literal(Value::String(value)));
+                    idx = end;
+                    continue;
+                }
+                _ => {
+                    let (word, end) = read_word(&chars, idx);
+                    let token = match word.as_str() {
+                        "true" => Token::Literal(Value::Bool(true)),
+                        "false" => Token::Literal(Value::Bool(false)),
+                        "null" => Token::Literal(Value::Null),
+                        _ => match serde_json::from_str::<serde_json::Number>(&word) {
+                            Ok(number) => Token::Literal(Value::Number(number)),
+                            Err(_) => Token::Extract(word),
+                        },
+                    };
+                    tokens.push(token);
+                    idx = end;
+                    continue;
+                }
+            };
+            tokens.push(token);
+            idx += len;
+        }
+
+        Ok(tokens)
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.parse_and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            lhs = Expr::And(Box::new(lhs), Box::new(self.parse_unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.next();
+                Ok(Expr::Not(Box::new(self.parse_unary()?)))
+            }
+            Some(Token::LParen) => {
+                self.next();
+                let expr = self.parse_or()?;
+                match self.next() {
+                    Some(Token::RParen) => Ok(expr),
+                    _ => Err(anyhow!("Expected a closing parenthesis")),
+                }
+            }
+            _ => self.parse_comparison(),
+        }
+    }
+
+    fn parse_operand(&mut self) -> Result<Operand> {
+        match self.next() {
+            Some(Token::Literal(value)) => Ok(Operand::Literal(value)),
+            Some(Token::Extract(extract)) => Ok(Operand::Extract(extract)),
+            Some(token) => Err(anyhow!("Expected a value, found {:?}", token)),
+            None => Err(anyhow!("Expected a value, found the end of the condition")),
+        }
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr> {
+        let lhs = self.parse_operand()?;
+        match self.peek() {
+            Some(Token::Compare(op)) => {
+                let op = *op;
+                self.next();
+                Ok(Expr::Compare(lhs, op, self.parse_operand()?))
+            }
+            _ => Ok(Expr::Truthy(lhs)),
+        }
+    }
+}
+
+fn parse(text: &str) -> Result<Expr> {
+    let mut parser = Parser {
+        tokens: lexer::tokenize(text)?,
+        pos: 0,
+    };
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(anyhow!("Unexpected {:?} in condition [{}]", token, text)),
+    }
+}
+
+fn resolve(operand: &Operand, resolver: &dyn Resolver) -> Result<Value> {
+    match operand {
+        Operand::Literal(value) => Ok(value.clone()),
+        Operand::Extract(extract) => resolver.extract(extract),
+    }
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty() && s != "false",
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty(),
+    }
+}
+
+/*
+    Extracted values are (currently) always strings, so allow numbers, booleans
+    and nulls to compare equal to their string forms.
+*/
+fn loose_eq(lhs: &Value, rhs: &Value) -> bool {
+    if lhs == rhs {
+        return true;
+    }
+    match (lhs, rhs) {
+        (Value::String(s), other) | (other, Value::String(s)) => match other {
+            Value::Number(_) => as_number(other) == s.trim().parse::<f64>().ok(),
+            Value::Bool(b) => s == &b.to_string(),
+            Value::Null => s == "null",
+            _ => false,
+        },
+        (Value::Number(_), Value::Number(_)) => as_number(lhs) == as_number(rhs),
+        _ => false,
+    }
+}
+
+fn compare(lhs: &Value, rhs: &Value) -> Result<Ordering> {
+    if let (Some(l), Some(r)) = (as_number(lhs), as_number(rhs)) {
+        return l
+            .partial_cmp(&r)
+            .ok_or_else(|| anyhow!("Cannot compare {} and {}", lhs, rhs));
+    }
+    match (lhs, rhs) {
+        (Value::String(l), Value::String(r)) => Ok(l.cmp(r)),
+        _ => Err(anyhow!("Cannot order {} and {}", lhs, rhs)),
+    }
+}
+
+fn evaluate_expr(expr: &Expr, resolver: &dyn Resolver) -> Result<bool> {
+    match expr {
+        Expr::And(lhs, rhs) => Ok(evaluate_expr(lhs, resolver)? && evaluate_expr(rhs, resolver)?),
+        Expr::Or(lhs, rhs) => Ok(evaluate_expr(lhs, resolver)? || evaluate_expr(rhs, resolver)?),
+        Expr::Not(inner) => Ok(!evaluate_expr(inner, resolver)?),
+        Expr::Truthy(operand) => Ok(is_truthy(&resolve(operand, resolver)?)),
+        Expr::Compare(lhs, op, rhs) => {
+            let lhs = resolve(lhs, resolver)?;
+            let rhs = resolve(rhs, resolver)?;
+            match op {
+                CompareOp::Eq => Ok(loose_eq(&lhs, &rhs)),
+                CompareOp::Ne => Ok(!loose_eq(&lhs, &rhs)),
+                CompareOp::Lt => Ok(compare(&lhs, &rhs)? == Ordering::Less),
+                CompareOp::Le => Ok(compare(&lhs, &rhs)? != Ordering::Greater),
+                CompareOp::Gt => Ok(compare(&lhs, &rhs)? == Ordering::Greater),
+                CompareOp::Ge => Ok(compare(&lhs, &rhs)? != Ordering::Less),
+            }
+        }
+    }
+}
+
+fn check_extractors(expr: &Expr) -> Result<()> {
+    match expr {
+        Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) => {
+            check_extractors(lhs)?;
+            check_extractors(rhs)
+        }
+        Expr::Not(inner) => check_extractors(inner),
+        Expr::Truthy(operand) => check_operand(operand),
+        Expr::Compare(lhs, _, rhs) => {
+            check_operand(lhs)?;
+            check_operand(rhs)
+        }
+    }
+}
+
+fn check_operand(operand: &Operand) -> Result<()> {
+    match operand {
+        Operand::Extract(extract) if property(extract, &PropertyMap::new()).is_none() => {
+            extractor::check(extract)
+        }
+        _ => Ok(()),
+    }
+}
+
+// A condition parsed up front, so mistakes are found before it's evaluated
+#[derive(Debug, Clone)]
+pub struct Condition(Expr);
+
+impl Condition {
+    // Fails on bad syntax, and on operands that don't name an extractor
+    pub fn parse(text: &str) -> Result<Condition> {
+        let expr = parse(text)?;
+        check_extractors(&expr)?;
+        Ok(Condition(expr))
+    }
+
+    pub fn evaluate(&self, resolver: &dyn Resolver) -> Result<bool> {
+        evaluate_expr(&self.0, resolver)
+    }
+}
+
+pub fn evaluate(condition: &str, resolver: &dyn Resolver) -> Result<bool> {
+    let expr = parse(condition)?;
+    evaluate_expr(&expr, resolver)
+}
+
+#[cfg(test)]
+mod test {
+    use super::Resolver;
+    use crate::common::PropertyMap;
+    use crate::response::ResponseBuilder;
+    use anyhow::{anyhow, Result};
+    use serde_json::{json, Value};
+
+    struct MapResolver(Value);
+
+    impl Resolver for MapResolver {
+        fn extract(&self, extractor: &str) -> Result<Value> {
+            self.0
+                .get(extractor)
+                .cloned()
+                .ok_or_else(|| anyhow!("Unknown extractor {}", extractor))
+        }
+    }
+
+    fn resolver() -> MapResolver {
+        MapResolver(json!({
+            "json:status": "done",
+            "json:count": "3",
+            "json:items[?(@.id == 5)].name": "five",
+            "json:ready": "true",
+        }))
+    }
+
+    #[test]
+    fn string_equality() {
+        let value = super::evaluate(r#"json:status == "done""#, &resolver());
+        assert!(value.is_ok(), "Evaluating failed: {:?}", value.unwrap_err());
+        assert!(value.unwrap());
+
+        let value = super::evaluate(r#"json:status != "done""#, &resolver());
+        assert!(!value.unwrap());
+    }
+
+    #[test]
+    fn numeric_comparison() {
+        assert!(super::evaluate("json:count == 3", &resolver()).unwrap());
+        assert!(super::evaluate("json:count >= 3", &resolver()).unwrap());
+        assert!(super::evaluate("json:count < 10", &resolver()).unwrap());
+        assert!(!super::evaluate("json:count > 3", &resolver()).unwrap());
+    }
+
+    #[test]
+    fn logical_operators() {
+        let condition = r#"(json:status == "pending" || json:count > 2) && !json:ready == false"#;
+        let value = super::evaluate(condition, &resolver());
+        assert!(value.is_ok(), "Evaluating failed: {:?}", value.unwrap_err());
+        assert!(value.unwrap());
+    }
+
+    #[test]
+    fn truthy_operand() {
+        assert!(super::evaluate("json:ready", &resolver()).unwrap());
+        assert!(!super::evaluate("!json:ready", &resolver()).unwrap());
+    }
+
+    #[test]
+    fn extractor_with_spaces_and_parens() {
+        let value = super::evaluate(r#"(json:items[?(@.id == 5)].name == "five")"#, &resolver());
+        assert!(value.is_ok(), "Evaluating failed: {:?}", value.unwrap_err());
+        assert!(value.unwrap());
+    }
+
+    #[test]
+    fn unknown_extractor_errors() {
+        assert!(super::evaluate(r#"json:missing == "done""#, &resolver()).is_err());
+    }
+
+    #[test]
+    fn malformed_condition_errors() {
+        assert!(super::evaluate(r#"json:status == "done"#, &resolver()).is_err());
+        assert!(super::evaluate("(json:status == 1", &resolver()).is_err());
+        assert!(super::evaluate("json:status == 1 2", &resolver()).is_err());
+    }
+
+    #[test]
+    fn conditions_checked_when_parsed() {
+        assert!(super::Condition::parse(r#"json:status == "done" || {retries} > 3"#).is_ok());
+        assert!(super::Condition::parse(r#"json:status == "done"#).is_err());
+        assert!(super::Condition::parse(r#"jsno:status == "done""#).is_err());
+        assert!(super::Condition::parse("done").is_err());
+
+        let condition = super::Condition::parse("json:count >= 3").unwrap();
+        assert!(condition.evaluate(&resolver()).unwrap());
+    }
+
+    #[test]
+    fn response_resolver() {
+        let response = ResponseBuilder::new()
+            .body(r#"{"status": "done"}"#.as_bytes().to_vec())
+            .build();
+        let mut properties = PropertyMap::new();
+        let value = super::evaluate(r#"json:status == "done""#, &(&response, &properties));
+        assert!(value.is_ok(), "Evaluating failed: {:?}", value.unwrap_err());
+        assert!(value.unwrap());
+
+        properties.insert("expected".to_owned(), json!("done"));
+        let value = super::evaluate("json:status == {expected}", &(&response, &properties));
+        assert!(value.unwrap());
+    }
+
+    #[test]
+    fn property_resolver() {
+        let mut properties = PropertyMap::new();
+        properties.insert("status_of.login".to_owned(), json!(200));
+        properties.insert("feature_flag".to_owned(), json!("on"));
+
+        let condition = r#"{status_of.login} == 200 && {feature_flag} == "on""#;
+        assert!(super::evaluate(condition, &properties).unwrap());
+        assert!(!super::evaluate("{status_of.login} >= 400", &properties).unwrap());
+        assert!(super::evaluate("{missing} == null", &properties).unwrap());
+        assert!(super::evaluate("json:status == 1", &properties).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+use crate::client::{ClientOptions, HttpClient, ProxySettings};
+use crate::cookies::CookieJar;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/*
+  State that lives for the whole run and is shared by every request,
+  as opposed to the per-request data in `Request`.
+*/
+pub struct RunContext {
+    options: ClientOptions,
+    proxy: ProxySettings,
+    clients: Mutex<HashMap<ProxySettings, HttpClient>>,
+    cookies: CookieJar,
+}
+
+impl RunContext {
+    pub fn new(options: ClientOptions, proxy: ProxySettings, cookies: CookieJar) -> Self {
+        RunContext {
+            options,
+            proxy,
+            clients: Mutex::new(HashMap::new()),
+            cookies,
+        }
+    }
+
+    /*
+        The client to use with the given proxy settings, or the run's default ones.
+        Given settings still bypass the hosts the run's do. Clients are created on
+        first use and shared from then on, so requests with the same settings
+        share a connection pool.
+    */
+    pub fn client(&self, proxy: Option<&ProxySettings>) -> HttpClient {
+        let proxy = match proxy {
+            Some(proxy) => &proxy.merged_no_proxy(&self.proxy),
+            None => &self.proxy,
+        };
+        self.clients
+            .lock()
+            .unwrap()
+            .entry(proxy.clone())
+            .or_insert_with(|| HttpClient::new(&self.options, proxy.clone()))
+            .clone()
+    }
+
+    pub fn cookies(&self) -> &CookieJar {
+        &self.cookies
+    }
+}
@@ -0,0 +1,327 @@
+use crate::common::StringMap;
+use anyhow::{anyhow, Context, Result};
+use cookie_store::{CookieStore, RawCookie};
+use hyper::header::{self, HeaderMap, HeaderValue};
+use std::fs;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use url::Url;
+
+/*
+  Cookie jar shared by every request in a run. Cookies set by a response are
+  sent on later requests that match their domain/path, skipping expired ones
+  and secure cookies over plain HTTP. The jar can be disabled entirely, or
+  loaded from and saved back to a file so it survives between runs.
+*/
+pub struct CookieJar {
+    store: Option<Mutex<CookieStore>>,
+    path: Option<PathBuf>,
+}
+
+fn parse_set_cookies(headers: &HeaderMap) -> Vec<RawCookie<'static>> {
+    headers
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .filter_map(|value| RawCookie::parse(value.to_owned()).ok())
+        .collect()
+}
+
+// Collects the cookies set while following a single request's redirect chain
+pub struct ChainCookies {
+    store: CookieStore,
+}
+
+impl ChainCookies {
+    pub fn new() -> Self {
+        ChainCookies {
+            store: CookieStore::default(),
+        }
+    }
+
+    pub fn store(&mut self, url: &Url, headers: &HeaderMap) {
+        self.store
+            .store_response_cookies(parse_set_cookies(headers).into_iter(), url);
+    }
+
+    pub fn values(&self) -> StringMap {
+        self.store
+            .iter_unexpired()
+            .map(|cookie| (cookie.name().to_owned(), cookie.value().to_owned()))
+            .collect()
+    }
+}
+
+impl CookieJar {
+    pub fn disabled() -> Self {
+        CookieJar {
+            store: None,
+            path: None,
+        }
+    }
+
+    pub fn in_memory() -> Self {
+        CookieJar {
+            store: Some(Mutex::new(CookieStore::default())),
+            path: None,
+        }
+    }
+
+    // Loads the jar from `path` if it exists; `save` writes it back there
+    pub fn persistent(path: &Path) -> Result<Self> {
+        Ok(CookieJar {
+            store: Some(Mutex::new(Self::load_file(path)?)),
+            path: Some(path.to_owned()),
+        })
+    }
+
+    fn load_file(path: &Path) -> Result<CookieStore> {
+        if !path.exists() {
+            return Ok(CookieStore::default());
+        }
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open cookie jar {}", path.display()))?;
+        Self::load(BufReader::new(file))
+            .with_context(|| format!("Failed to load cookie jar {}", path.display()))
+    }
+
+    // Back to how the run started, empty or as loaded, as the jar is only saved once it's over
+    pub fn reset(&self) -> Result<()> {
+        let Some(store) = &self.store else {
+            return Ok(());
+        };
+        let fresh = match &self.path {
+            Some(path) => Self::load_file(path)?,
+            None => CookieStore::default(),
+        };
+        *store.lock().unwrap() = fresh;
+        Ok(())
+    }
+
+    fn load<R: BufRead>(reader: R) -> Result<CookieStore> {
+        CookieStore::load_json(reader).map_err(|e| anyhow!(e))
+    }
+
+    fn write<W: Write>(store: &CookieStore, writer: &mut W) -> Result<()> {
+        // Session cookies are kept too, as a run is effectively one long session
+        for cookie in store.iter_unexpired() {
+            writeln!(writer, "{}", serde_json::to_string(cookie)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let (store, path) = match (&self.store, &self.path) {
+            (Some(store), Some(path)) => (store, path),
+            _ => return Ok(()),
+        };
+
+        let file = fs::File::create(path)
+            .with_context(|| format!("Failed to create cookie jar {}", path.display()))?;
+        let store = store.lock().unwrap();
+        Self::write(&store, &mut BufWriter::new(file))
+            .with_context(|| format!("Failed to save cookie jar {}", path.display()))
+    }
+
+    // Adds any cookies for `url` to the request, after whatever Cookie header it already has
+    pub fn add_request_cookies(&self, url: &Url, headers: &mut HeaderMap) -> Result<()> {
+        let store = match &self.store {
+            Some(store) => store.lock().unwrap(),
+            None => return Ok(()),
+        };
+
+        let mut values: Vec<String> = headers
+            .get_all(header::COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(|value| value.to_owned())
+            .collect();
+        values.extend(
+            store
+                .get_request_values(url)
+                .map(|(name, value)| format!("{}={}", name, value)),
+        );
+
+        if !values.is_empty() {
+            headers.insert(
+                header::COOKIE,
+                HeaderValue::from_str(&values.join("; "))
+                    .with_context(|| "Invalid characters in cookie values")?,
+            );
+        }
+        Ok(())
+    }
+
+    pub fn store_response_cookies(&self, url: &Url, headers: &HeaderMap) {
+        if let Some(store) = &self.store {
+            store
+                .lock()
+                .unwrap()
+                .store_response_cookies(parse_set_cookies(headers).into_iter(), url);
+        }
+    }
+
+    // Name/value pairs of the cookies that would be sent to `url`
+    pub fn values(&self, url: &Url) -> StringMap {
+        match &self.store {
+            Some(store) => store
+                .lock()
+                .unwrap()
+                .get_request_values(url)
+                .map(|(name, value)| (name.to_owned(), value.to_owned()))
+                .collect(),
+            None => StringMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ChainCookies, CookieJar};
+    use cookie_store::{Cookie, CookieStore};
+    use hyper::header::{self, HeaderMap, HeaderValue};
+    use std::io::BufReader;
+    use std::sync::Mutex;
+    use url::Url;
+
+    fn set_cookies(cookies: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for cookie in cookies {
+            headers.append(header::SET_COOKIE, HeaderValue::from_static(cookie));
+        }
+        headers
+    }
+
+    fn request_cookies(jar: &CookieJar, url: &str) -> Option<String> {
+        let mut headers = HeaderMap::new();
+        jar.add_request_cookies(&Url::parse(url).unwrap(), &mut headers)
+            .unwrap();
+        headers
+            .get(header::COOKIE)
+            .map(|value| value.to_str().unwrap().to_owned())
+    }
+
+    #[test]
+    fn respects_domain_and_path() {
+        let jar = CookieJar::in_memory();
+        jar.store_response_cookies(
+            &Url::parse("http://api.example.com/login").unwrap(),
+            &set_cookies(&["session=abc; Path=/", "scoped=1; Path=/admin"]),
+        );
+
+        assert_eq!(
+            request_cookies(&jar, "http://api.example.com/users"),
+            Some("session=abc".to_owned())
+        );
+        let admin = request_cookies(&jar, "http://api.example.com/admin/x").unwrap();
+        assert!(admin.contains("session=abc") && admin.contains("scoped=1"));
+        assert_eq!(request_cookies(&jar, "http://other.example.com/"), None);
+    }
+
+    #[test]
+    fn secure_and_expired_cookies() {
+        let jar = CookieJar::in_memory();
+        jar.store_response_cookies(
+            &Url::parse("https://example.com/").unwrap(),
+            &set_cookies(&["secure=1; Secure", "plain=2", "gone=3; Max-Age=0"]),
+        );
+
+        assert_eq!(
+            request_cookies(&jar, "http://example.com/"),
+            Some("plain=2".to_owned())
+        );
+        let secure = request_cookies(&jar, "https://example.com/").unwrap();
+        assert!(secure.contains("secure=1") && !secure.contains("gone"));
+    }
+
+    #[test]
+    fn keeps_existing_cookie_header() {
+        let jar = CookieJar::in_memory();
+        let url = Url::parse("http://example.com/").unwrap();
+        jar.store_response_cookies(&url, &set_cookies(&["jar=1"]));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_static("manual=0"));
+        jar.add_request_cookies(&url, &mut headers).unwrap();
+        assert_eq!(headers.get(header::COOKIE).unwrap(), "manual=0; jar=1");
+    }
+
+    #[test]
+    fn disabled_jar() {
+        let jar = CookieJar::disabled();
+        let url = Url::parse("http://example.com/").unwrap();
+        jar.store_response_cookies(&url, &set_cookies(&["session=abc"]));
+        assert_eq!(request_cookies(&jar, "http://example.com/"), None);
+        assert!(jar.values(&url).is_empty());
+    }
+
+    #[test]
+    fn save_and_load() {
+        let jar = CookieJar::in_memory();
+        let url = Url::parse("http://example.com/").unwrap();
+        jar.store_response_cookies(&url, &set_cookies(&["session=abc", "gone=1; Max-Age=0"]));
+
+        let mut data = Vec::new();
+        CookieJar::write(&jar.store.as_ref().unwrap().lock().unwrap(), &mut data).unwrap();
+
+        let store = CookieJar::load(BufReader::new(data.as_slice())).unwrap();
+        let loaded = CookieJar {
+            store: Some(Mutex::new(store)),
+            path: None,
+        };
+        assert_eq!(
+            request_cookies(&loaded, "http://example.com/"),
+            Some("session=abc".to_owned())
+        );
+    }
+
+    #[test]
+    fn reset() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let jar = CookieJar::in_memory();
+        jar.store_response_cookies(&url, &set_cookies(&["session=alice"]));
+        jar.reset().unwrap();
+        assert_eq!(request_cookies(&jar, "http://example.com/"), None);
+
+        let path = crate::common::scratch_dir("reset").join("jar.json");
+        let saved = CookieJar::persistent(&path).unwrap();
+        saved.store_response_cookies(&url, &set_cookies(&["kept=1"]));
+        saved.save().unwrap();
+
+        let jar = CookieJar::persistent(&path).unwrap();
+        jar.store_response_cookies(&url, &set_cookies(&["session=alice"]));
+        jar.reset().unwrap();
+        assert_eq!(
+            request_cookies(&jar, "http://example.com/"),
+            Some("kept=1".to_owned())
+        );
+    }
+
+    #[test]
+    fn expired_cookies_not_saved() {
+        let url = Url::parse("http://example.com/").unwrap();
+        let cookies =
+            ["session=abc", "gone=1; Max-Age=0"].map(|cookie| Cookie::parse(cookie, &url));
+        let store = CookieStore::from_cookies(cookies, true).unwrap();
+        assert_eq!(store.iter_any().count(), 2);
+
+        let mut data = Vec::new();
+        CookieJar::write(&store, &mut data).unwrap();
+        let saved = String::from_utf8(data).unwrap();
+        assert_eq!(saved.lines().count(), 1, "{}", saved);
+        assert!(saved.contains("session=abc"));
+    }
+
+    #[test]
+    fn chain_cookies() {
+        let mut chain = ChainCookies::new();
+        chain.store(
+            &Url::parse("http://example.com/login").unwrap(),
+            &set_cookies(&["session=abc; Path=/login", "gone=1; Max-Age=0"]),
+        );
+        let values = chain.values();
+        assert_eq!(values.get("session"), Some(&"abc".to_owned()));
+        assert!(!values.contains_key("gone"));
+    }
+}
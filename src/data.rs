@@ -0,0 +1,136 @@
+use crate::common::PropertyMap;
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/*
+  Data driven runs. `--data` takes a CSV file with a header row, or a JSON
+  array of objects, and the collections run once per row with its columns as
+  properties. CSV cells are strings, JSON values keep their types.
+*/
+
+fn load_csv(data: &str) -> Result<Vec<PropertyMap>> {
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    let headers = reader.headers()?.clone();
+    reader
+        .records()
+        .enumerate()
+        .map(|(idx, record)| {
+            let record = record.with_context(|| format!("Failed to read row {}", idx + 1))?;
+            Ok(headers
+                .iter()
+                .zip(record.iter())
+                .map(|(column, cell)| (column.to_owned(), Value::from(cell)))
+                .collect())
+        })
+        .collect()
+}
+
+fn load_json(data: &str) -> Result<Vec<PropertyMap>> {
+    let rows: Vec<Value> = serde_json::from_str(data).with_context(|| "Expected a JSON array")?;
+    rows.into_iter()
+        .enumerate()
+        .map(|(idx, row)| match row {
+            Value::Object(row) => Ok(row.into_iter().collect()),
+            _ => Err(anyhow!("Row {} isn't a JSON object", idx + 1)),
+        })
+        .collect()
+}
+
+pub fn load(path: &Path) -> Result<Vec<PropertyMap>> {
+    let data = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read data from {}", path.display()))?;
+    let rows = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("csv") => load_csv(&data),
+        Some("json") => load_json(&data),
+        _ => Err(anyhow!("Data files are either .csv or .json")),
+    }
+    .with_context(|| format!("Failed to load {}", path.display()))?;
+
+    match rows.is_empty() {
+        true => Err(anyhow!("{} has no rows", path.display())),
+        false => Ok(rows),
+    }
+}
+
+// The array a `foreach` goes over, from the property it names
+pub fn foreach_items(name: &str, properties: &PropertyMap) -> Result<Vec<Value>> {
+    match properties.get(name) {
+        Some(Value::Array(items)) => Ok(items.clone()),
+        Some(other) => Err(anyhow!("foreach [{}] isn't an array: {}", name, other)),
+        None => Err(anyhow!("foreach [{}] isn't a property", name)),
+    }
+}
+
+/*
+  The properties one `foreach` pass sees: `item` and `item_index`, plus
+  `item.<field>` for each field when the item is an object.
+*/
+pub fn item_properties(index: usize, item: Value) -> PropertyMap {
+    let mut properties = PropertyMap::new();
+    properties.insert("item_index".to_owned(), Value::from(index));
+    if let Value::Object(fields) = &item {
+        for (field, value) in fields {
+            properties.insert(format!("item.{}", field), value.clone());
+        }
+    }
+    properties.insert("item".to_owned(), item);
+    properties
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::{scratch_dir, PropertyMap};
+    use serde_json::json;
+    use std::fs;
+
+    #[test]
+    fn csv_and_json() {
+        let tmp_dir = scratch_dir("csv_and_json");
+        fs::write(
+            tmp_dir.join("users.csv"),
+            "username,password\nalice,\"pa,ss\"\nbob,123\n",
+        )
+        .unwrap();
+        fs::write(
+            tmp_dir.join("users.json"),
+            r#"[{ "username": "alice", "age": 30 }, { "username": "bob", "tags": ["a"] }]"#,
+        )
+        .unwrap();
+
+        let rows = super::load(&tmp_dir.join("users.csv")).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0]["password"], "pa,ss");
+        assert_eq!(rows[1]["password"], "123");
+
+        let rows = super::load(&tmp_dir.join("users.json")).unwrap();
+        assert_eq!(rows[0]["age"], 30);
+        assert_eq!(rows[1]["tags"], json!(["a"]));
+
+        fs::write(tmp_dir.join("bad.json"), "[1]").unwrap();
+        assert!(super::load(&tmp_dir.join("bad.json")).is_err());
+        fs::write(tmp_dir.join("empty.csv"), "username\n").unwrap();
+        assert!(super::load(&tmp_dir.join("empty.csv")).is_err());
+        assert!(super::load(&tmp_dir.join("users.txt")).is_err());
+    }
+
+    #[test]
+    fn foreach() {
+        let mut properties = PropertyMap::new();
+        properties.insert("ids".to_owned(), json!([{ "id": 4 }, 5]));
+        properties.insert("name".to_owned(), json!("x"));
+
+        let items = super::foreach_items("ids", &properties).unwrap();
+        assert_eq!(items.len(), 2);
+        assert!(super::foreach_items("name", &properties).is_err());
+        assert!(super::foreach_items("missing", &properties).is_err());
+
+        let first = super::item_properties(0, items[0].clone());
+        assert_eq!(first["item.id"], 4);
+        assert_eq!(first["item_index"], 0);
+        let second = super::item_properties(1, items[1].clone());
+        assert_eq!(second["item"], 5);
+        assert_eq!(second.len(), 2);
+    }
+}
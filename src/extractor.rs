@@ -1,187 +1,118 @@
-use crate::common::StringMap;
+use crate::common::PropertyMap;
 use crate::response::Response;
 use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
 
-#[derive(Debug)]
-enum ExtractorTypes {
-    UNKNOWN,
-    HEADER,
-    JSON,
+/*
+    An entry in a request's `extract` map. Either just the extractor string, or
+    `{ "from": "json:id", "optional": true }` for a value the request can do without.
+    Anything not marked optional has to be extracted for the request to pass.
+*/
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Extraction {
+    Required(String),
+    Detailed {
+        from: String,
+        #[serde(default)]
+        optional: bool,
+    },
 }
 
-fn get_type(extract: &String) -> (ExtractorTypes, &str) {
-    if extract.starts_with("json:") {
-        return (ExtractorTypes::JSON, &extract[5..]);
-    } else if extract.starts_with("header:") {
-        return (ExtractorTypes::HEADER, &extract[7..]);
-    }
-    return (ExtractorTypes::UNKNOWN, extract);
-}
-
-mod json {
-    use crate::response::Response;
-    use anyhow::{anyhow, Context, Result};
-    use lazy_static::lazy_static;
-    use regex::Regex;
-    use serde_json::Value;
-
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"([A-Za-z]+)\[(\d+)\]")
-            .expect("Failed to create regex for JSON index parsing");
-    }
-
-    pub fn extract(extract_string: &str, response: &Response) -> Result<String> {
-        println!("JSON Parsing [{}]", extract_string);
-        let body = String::from_utf8(response.body().clone())?;
-
-        let mut v: Value = serde_json::from_str(&body.as_str())?;
-
-        for original_token in extract_string.split('.') {
-            let mut index: Option<usize> = None;
-            /*
-                Would be nice to not convert this to an owned string, but I can't
-                figure out a way of letting the compiler know that the string WILL
-                outlive the "Borrow" from the RE.captures.
-            */
-            let token = match RE.captures(&original_token) {
-                Some(captures) => {
-                    // Parse the second capture, the number, into a usize for later
-                    match &captures[2].parse::<usize>() {
-                        Ok(val) => {
-                            index = Some(*val);
-                        }
-                        Err(e) => {
-                            return Err(anyhow!(
-                                "Failed to parse [{}] as a usize: {}",
-                                &captures[1],
-                                e
-                            ))
-                        }
-                    };
-
-                    // Make the first capture group, the identifier, into a String
-                    captures[1].to_owned()
-                }
-                None => original_token.to_owned(),
-            };
-            match v.get(token) {
-                Some(value) => {
-                    // TODO: Anyway to _not_ clone this value?
-                    v = value.clone();
-                }
-                None => {
-                    return Err(anyhow!(
-                        "Couldn't find [{}] in the response body. Full Query [{}]",
-                        original_token,
-                        extract_string
-                    ))
-                }
-            };
-
-            if index.is_some() {
-                v = v
-                    .as_array()
-                    .with_context(|| format!("Failed to parse {} as an array", original_token))?
-                    .get(index.unwrap())
-                    .expect("Failed to get index")
-                    .clone();
-            }
-        }
-
-        if v.is_string() {
-            Ok(v.as_str().unwrap().to_owned())
-        } else {
-            Ok(v.to_string())
+impl Extraction {
+    pub fn from(&self) -> &str {
+        match self {
+            Extraction::Required(from) => from,
+            Extraction::Detailed { from, .. } => from,
         }
     }
 
-    #[cfg(test)]
-    mod test {
-        use crate::response::ResponseBuilder;
-
-        fn get_basic_string() -> &'static str {
-            r#"
-        {
-            "name": "John Doe",
-            "age": 43,
-            "phones": [
-                "+44 1234567",
-                "+44 2345678"
-            ]
-        }"#
+    pub fn optional(&self) -> bool {
+        match self {
+            Extraction::Required(_) => false,
+            Extraction::Detailed { optional, .. } => *optional,
         }
+    }
+}
 
-        #[test]
-        fn basic_string() {
-            let response = ResponseBuilder::new()
-                .body(get_basic_string().as_bytes().to_vec())
-                .build();
-            let to_extract = "name";
-            let value = super::extract(to_extract, &response);
-            assert!(value.is_ok(), "Extracting failed: {:?}", value.unwrap_err());
-            assert_eq!(value.unwrap(), "John Doe");
-        }
+pub type ExtractMap = HashMap<String, Extraction>;
 
-        #[test]
-        fn basic_int() {
-            let response = ResponseBuilder::new()
-                .body(get_basic_string().as_bytes().to_vec())
-                .build();
-            let to_extract = "age";
-            let value = super::extract(to_extract, &response);
-            assert!(value.is_ok(), "Extracting failed: {:?}", value.unwrap_err());
-            assert_eq!(value.unwrap(), "43");
-        }
-
-        #[test]
-        fn basic_index() {
-            let response = ResponseBuilder::new()
-                .body(get_basic_string().as_bytes().to_vec())
-                .build();
-            let to_extract = "phones[1]";
-            let value = super::extract(to_extract, &response);
-            assert!(value.is_ok(), "Extracting failed: {:?}", value.unwrap_err());
-            assert_eq!(value.unwrap(), "+44 2345678");
-        }
+// What came of a single extraction, kept around so it can be reported
+#[derive(Debug)]
+pub struct Extracted {
+    pub property: String,
+    pub from: String,
+    pub optional: bool,
+    pub value: Result<Value>,
+}
 
-        fn get_deep_object() -> &'static str {
-            r#"
-        {
-            "foo": {
-                "bar": {
-                    "baz": {
-                        "a": {
-                            "b": {
-                                "c": {
-                                    "d": 1066
-                                },
-                                "x": 6
-                            },
-                            "y": 5
-                        },
-                        "z": 4
-                    },
-                    "zab": 3
-                },
-                "rab": 2
-            },
-            "oof": 1
-        }"#
-        }
+#[derive(Debug)]
+enum ExtractorTypes {
+    Unknown,
+    Header,
+    Json,
+    JmesPath,
+    Regex,
+    XPath,
+    Css,
+    Redirects,
+    Cookie,
+    Status,
+    Elapsed,
+    Size,
+    Url,
+    Body,
+    Jwt,
+    Request,
+}
 
-        #[test]
-        fn deep_object() {
-            let response = ResponseBuilder::new()
-                .body(get_deep_object().as_bytes().to_vec())
-                .build();
-            let to_extract = "foo.bar.baz.a.b.c.d";
-            let value = super::extract(to_extract, &response);
-            assert!(value.is_ok(), "Extracting failed: {:?}", value.unwrap_err());
-            assert_eq!(value.unwrap(), "1066");
-        }
+fn get_type(extract: &str) -> (ExtractorTypes, &str) {
+    if let Some(view) = extract.strip_prefix("json:") {
+        return (ExtractorTypes::Json, view);
+    } else if let Some(view) = extract.strip_prefix("jmespath:") {
+        return (ExtractorTypes::JmesPath, view);
+    } else if let Some(view) = extract.strip_prefix("regex:") {
+        return (ExtractorTypes::Regex, view);
+    } else if let Some(view) = extract.strip_prefix("xpath:") {
+        return (ExtractorTypes::XPath, view);
+    } else if let Some(view) = extract.strip_prefix("css:") {
+        return (ExtractorTypes::Css, view);
+    } else if let Some(view) = extract.strip_prefix("header:") {
+        return (ExtractorTypes::Header, view);
+    } else if let Some(view) = extract.strip_prefix("redirects:") {
+        return (ExtractorTypes::Redirects, view);
+    } else if let Some(view) = extract.strip_prefix("cookie:") {
+        return (ExtractorTypes::Cookie, view);
+    } else if let Some(view) = extract.strip_prefix("status:") {
+        return (ExtractorTypes::Status, view);
+    } else if let Some(view) = extract.strip_prefix("elapsed_ms:") {
+        return (ExtractorTypes::Elapsed, view);
+    } else if let Some(view) = extract.strip_prefix("size:") {
+        return (ExtractorTypes::Size, view);
+    } else if let Some(view) = extract.strip_prefix("url:") {
+        return (ExtractorTypes::Url, view);
+    } else if let Some(view) = extract.strip_prefix("body:") {
+        return (ExtractorTypes::Body, view);
+    } else if let Some(view) = extract.strip_prefix("jwt:") {
+        return (ExtractorTypes::Jwt, view);
+    } else if let Some(view) = extract.strip_prefix("request.") {
+        return (ExtractorTypes::Request, view);
     }
+    (ExtractorTypes::Unknown, extract)
 }
 
+mod css;
+mod jmespath;
+pub mod json;
+pub mod jwt;
+mod metadata;
+mod regex;
+mod sent;
+mod transform;
+mod xpath;
+
 mod headers {
     use crate::response::Response;
     use anyhow::{anyhow, Result};
@@ -189,8 +120,9 @@ mod headers {
     pub fn extract(extract_string: &str, response: &Response) -> Result<String> {
         println!("Header Parsing [{}]", extract_string);
 
+        // Header names are case-insensitive, and hyper hands them back lowercased
         for (header, value) in response.headers() {
-            if header == extract_string {
+            if header.eq_ignore_ascii_case(extract_string) {
                 return Ok(value.to_string());
             }
         }
@@ -219,27 +151,228 @@ mod headers {
     }
 }
 
-pub fn extract(to_extract: &StringMap, response: &Response) -> Result<StringMap> {
-    let mut map = StringMap::new();
+mod cookies {
+    use crate::response::Response;
+    use anyhow::{anyhow, Result};
 
-    for (prop, extract) in to_extract {
-        let (extract_type, view) = get_type(extract);
-        let result = match extract_type {
-            ExtractorTypes::JSON => json::extract(view, response),
-            ExtractorTypes::HEADER => headers::extract(view, response),
-            _ => Err(anyhow!("Unknown extractor type")),
-        };
+    pub fn extract(extract_string: &str, response: &Response) -> Result<String> {
+        println!("Cookie Parsing [{}]", extract_string);
 
-        match result {
-            Ok(value) => {
-                println!("Extracted [{}={}] from response body", prop, value);
-                map.insert(prop.clone(), value);
-            }
-            Err(e) => {
-                println!("Failed to extract {} from response: {}", view, e);
-            }
+        match response.cookies().get(extract_string) {
+            Some(value) => Ok(value.clone()),
+            None => Err(anyhow!("Could not find cookie [{}]", extract_string)),
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use crate::response::ResponseBuilder;
+        use std::collections::HashMap;
+
+        #[test]
+        fn basic() {
+            let mut cookies = HashMap::new();
+            cookies.insert("session".to_owned(), "abc".to_owned());
+            let response = ResponseBuilder::new().cookies(cookies).build();
+
+            let value = super::extract("session", &response);
+            assert!(value.is_ok(), "Extracted failed: {:?}", value.unwrap_err());
+            assert_eq!(value.unwrap(), "abc");
+            assert!(super::extract("Session", &response).is_err());
+        }
+    }
+}
+
+/*
+    Exposes the redirect chain followed to get the response.
+    `count` gives the number of hops, `N` the URL hop N went to, and
+    `N.status` / `N.from` / `N.to` the individual parts of a hop.
+*/
+mod redirects {
+    use crate::response::Response;
+    use anyhow::{anyhow, Context, Result};
+    use serde_json::Value;
+
+    pub fn extract(extract_string: &str, response: &Response) -> Result<Value> {
+        println!("Redirect Parsing [{}]", extract_string);
+
+        if extract_string == "count" {
+            return Ok(Value::from(response.redirects().len()));
+        }
+
+        let (index, field) = match extract_string.split_once('.') {
+            Some((index, field)) => (index, field),
+            None => (extract_string, "to"),
         };
+        let index = index
+            .parse::<usize>()
+            .with_context(|| format!("Failed to parse [{}] as a redirect index", index))?;
+        let redirect = response.redirects().get(index).with_context(|| {
+            format!(
+                "Redirect [{}] out of range, only {} followed",
+                index,
+                response.redirects().len()
+            )
+        })?;
+
+        match field {
+            "status" => Ok(Value::from(redirect.status)),
+            "from" => Ok(Value::from(redirect.from.as_str())),
+            "to" => Ok(Value::from(redirect.to.as_str())),
+            _ => Err(anyhow!("Unknown redirect field [{}]", field)),
+        }
     }
 
-    Ok(map)
+    #[cfg(test)]
+    mod test {
+        use crate::response::{Redirect, ResponseBuilder};
+
+        #[test]
+        fn chain() {
+            let response = ResponseBuilder::new()
+                .redirects(vec![
+                    Redirect {
+                        status: 302,
+                        from: "http://a/1".to_owned(),
+                        to: "http://a/2".to_owned(),
+                    },
+                    Redirect {
+                        status: 308,
+                        from: "http://a/2".to_owned(),
+                        to: "http://b/3".to_owned(),
+                    },
+                ])
+                .build();
+
+            assert_eq!(super::extract("count", &response).unwrap(), 2);
+            assert_eq!(super::extract("1", &response).unwrap(), "http://b/3");
+            assert_eq!(super::extract("0.status", &response).unwrap(), 302);
+            assert_eq!(super::extract("1.from", &response).unwrap(), "http://a/2");
+            assert!(super::extract("2", &response).is_err());
+            assert!(super::extract("0.method", &response).is_err());
+        }
+    }
+}
+
+// Run a single extractor string, such as `json:some.value | lowercase`, against the response
+pub fn extract_value(
+    extract: &str,
+    response: &Response,
+    properties: &PropertyMap,
+) -> Result<Value> {
+    let (extract, transforms) = transform::split(extract);
+    let (extract_type, view) = get_type(extract);
+    let value = match extract_type {
+        ExtractorTypes::Json => json::extract(view, response),
+        ExtractorTypes::JmesPath => jmespath::extract(view, response),
+        ExtractorTypes::Regex => regex::extract(view, response).map(Value::String),
+        ExtractorTypes::XPath => xpath::extract(view, response),
+        ExtractorTypes::Css => css::extract(view, response),
+        ExtractorTypes::Header => headers::extract(view, response).map(Value::String),
+        ExtractorTypes::Redirects => redirects::extract(view, response),
+        ExtractorTypes::Cookie => cookies::extract(view, response).map(Value::String),
+        ExtractorTypes::Status => metadata::status(view, response),
+        ExtractorTypes::Elapsed => metadata::elapsed(view, response),
+        ExtractorTypes::Size => metadata::size(view, response),
+        ExtractorTypes::Url => metadata::url(view, response).map(Value::String),
+        ExtractorTypes::Body => metadata::body(view, response).map(Value::String),
+        ExtractorTypes::Jwt => jwt::extract(view, response, properties),
+        ExtractorTypes::Request => sent::extract(view, response, properties),
+        _ => Err(anyhow!("Unknown extractor type")),
+    }?;
+
+    transform::apply(&transforms, value)
+}
+
+// Fails for extractor strings that don't start with a known type, e.g. `jsno:id`
+pub fn check(extract: &str) -> Result<()> {
+    let (extract, _) = transform::split(extract);
+    match get_type(extract) {
+        (ExtractorTypes::Unknown, _) => Err(anyhow!("Unknown extractor type [{}]", extract)),
+        _ => Ok(()),
+    }
+}
+
+// Runs every extraction, in property order, whether or not the others worked
+pub fn extract(
+    to_extract: &ExtractMap,
+    response: &Response,
+    properties: &PropertyMap,
+) -> Vec<Extracted> {
+    let mut results: Vec<Extracted> = to_extract
+        .iter()
+        .map(|(property, extraction)| Extracted {
+            property: property.clone(),
+            from: extraction.from().to_owned(),
+            optional: extraction.optional(),
+            value: extract_value(extraction.from(), response, properties),
+        })
+        .collect();
+    results.sort_by(|a, b| a.property.cmp(&b.property));
+    results
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ExtractMap, Extraction};
+    use crate::common::PropertyMap;
+    use crate::response::ResponseBuilder;
+
+    #[test]
+    fn required_and_optional() {
+        let extract: ExtractMap = serde_json::from_str(
+            r#"{
+                "id": "json:id",
+                "name": { "from": "json:name", "optional": true },
+                "missing": { "from": "json:missing" }
+            }"#,
+        )
+        .unwrap();
+        assert!(matches!(extract["id"], Extraction::Required(_)));
+        assert!(extract["name"].optional());
+        assert!(!extract["missing"].optional());
+
+        let response = ResponseBuilder::new()
+            .body(br#"{ "id": 5, "name": "five" }"#.to_vec())
+            .build();
+        let results = super::extract(&extract, &response, &PropertyMap::new());
+        let properties: Vec<&str> = results.iter().map(|r| r.property.as_str()).collect();
+        assert_eq!(properties, ["id", "missing", "name"]);
+        assert_eq!(results[0].value.as_ref().unwrap(), 5);
+        assert!(results[1].value.is_err());
+        assert_eq!(results[2].value.as_ref().unwrap(), "five");
+    }
+
+    #[test]
+    fn malformed_bodies_are_errors() {
+        let response = ResponseBuilder::new().body(b"{ not json".to_vec()).build();
+        let properties = PropertyMap::new();
+        let extract = |extract: &str| super::extract_value(extract, &response, &properties);
+        assert!(extract("json:id").is_err());
+        assert!(extract("jmespath:id").is_err());
+        assert!(extract("xpath://id").is_err());
+        assert!(extract("json:[99]").is_err());
+        assert!(extract("jwt:json:token#sub").is_err());
+        assert!(extract("nope:id").is_err());
+    }
+
+    #[test]
+    fn piped_transforms() {
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("authorization".to_owned(), "Bearer ABC.def".to_owned());
+        let response = ResponseBuilder::new()
+            .headers(headers)
+            .body(br#"{ "count": "12" }"#.to_vec())
+            .build();
+        let properties = PropertyMap::new();
+
+        let value = super::extract_value(
+            r#"header:Authorization | trim_prefix("Bearer ") | lowercase"#,
+            &response,
+            &properties,
+        );
+        assert_eq!(value.unwrap(), "abc.def");
+        let value = super::extract_value("json:count|to_number", &response, &properties);
+        assert_eq!(value.unwrap(), 12);
+    }
 }
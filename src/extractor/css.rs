@@ -0,0 +1,143 @@
+use crate::response::Response;
+use anyhow::{anyhow, Result};
+use scraper::{Html, Selector};
+use serde_json::Value;
+
+/*
+  CSS selectors against an HTML body, giving the text of the matching
+  elements, or one of their attributes with an `@name` suffix, e.g.
+  `css:input[name=csrf_token]@value`. A selector whose last element has an id,
+  such as `form#login@action`, gives the one value it finds. Anything else gives
+  a JSON array of the matches, however many there are.
+*/
+
+// Splits off a trailing `@attribute`, ignoring any `@` inside the selector's brackets
+fn split_attribute(query: &str) -> (&str, Option<&str>) {
+    let brackets_end = query.rfind(']').map(|end| end + 1).unwrap_or(0);
+    match query[brackets_end..].rfind('@') {
+        Some(at) => {
+            let at = brackets_end + at;
+            (query[..at].trim(), Some(query[at + 1..].trim()))
+        }
+        None => (query.trim(), None),
+    }
+}
+
+// Whether the last element the selector picks has to have an id, so there's only one
+fn is_singular(selector: &str) -> bool {
+    let mut depth = 0;
+    let mut quote = None;
+    let mut last = String::new();
+    for c in selector.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '[' | '(') => depth += 1,
+            (None, ']' | ')') => depth -= 1,
+            (None, ',') if depth == 0 => return false,
+            (None, ' ' | '>' | '+' | '~') if depth == 0 => last.clear(),
+            (None, c) if depth == 0 => last.push(c),
+            _ => {}
+        }
+    }
+    last.contains('#')
+}
+
+pub fn extract(extract_string: &str, response: &Response) -> Result<Value> {
+    println!("CSS Parsing [{}]", extract_string);
+    let body = response
+        .text()
+        .ok_or_else(|| anyhow!("Body is binary, not HTML"))?;
+
+    let (selector, attribute) = split_attribute(extract_string);
+    let parsed = Selector::parse(selector)
+        .map_err(|e| anyhow!("Invalid CSS selector [{}]: {}", selector, e))?;
+    let document = Html::parse_document(&body);
+
+    let mut values = document
+        .select(&parsed)
+        .filter_map(|element| match attribute {
+            Some(attribute) => element.value().attr(attribute).map(Value::from),
+            None => Some(Value::from(element.text().collect::<String>().trim())),
+        });
+    if !is_singular(selector) {
+        return Ok(Value::Array(values.collect()));
+    }
+    values
+        .next()
+        .ok_or_else(|| anyhow!("[{}] matched nothing", extract_string))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::response::ResponseBuilder;
+    use serde_json::json;
+
+    #[test]
+    fn selectors() {
+        let response = ResponseBuilder::new()
+            .body(
+                br#"<html><body>
+                    <form id="login" action="/login">
+                        <input type="hidden" name="csrf_token" value="t0k3n">
+                        <a href="mailto:help@example.com">Help</a>
+                    </form>
+                    <ul><li> One </li><li>Two</li></ul>
+                </body></html>"#
+                    .to_vec(),
+            )
+            .build();
+        let extract = |query: &str| super::extract(query, &response).unwrap();
+
+        assert_eq!(
+            extract("#login input[name=csrf_token]@value"),
+            json!(["t0k3n"])
+        );
+        assert_eq!(extract("form#login@action"), "/login");
+        assert_eq!(extract("a[href^='mailto:help@']"), json!(["Help"]));
+        assert_eq!(extract("ul li:first-child"), json!(["One"]));
+        assert_eq!(extract("li"), json!(["One", "Two"]));
+        assert_eq!(extract("input@missing"), json!([]));
+        assert_eq!(extract("table"), json!([]));
+        assert!(super::extract("#login@missing", &response).is_err());
+        assert!(super::extract("#signup", &response).is_err());
+        assert!(super::extract("[[", &response).is_err());
+    }
+
+    #[test]
+    fn one_match() {
+        let response = ResponseBuilder::new()
+            .body(br#"<ul id="items"><li class="item">Tea</li></ul>"#.to_vec())
+            .build();
+        let extract = |query: &str| super::extract(query, &response).unwrap();
+
+        // Only one item on the page, but `li.item` could find more on another
+        assert_eq!(extract("li.item"), json!(["Tea"]));
+        assert_eq!(extract("ul#items"), "Tea");
+        assert_eq!(extract("#items@id"), "items");
+    }
+
+    #[test]
+    fn singular_selectors() {
+        for selector in [
+            "#a",
+            "div#a",
+            "ul > li#a.b",
+            "[data-x='#'] #a",
+            "form #a:first-child",
+        ] {
+            assert!(super::is_singular(selector), "{}", selector);
+        }
+        for selector in [
+            "li",
+            "#a li",
+            "#a > li",
+            "#a, #b",
+            "a[href='#top']",
+            "li:not(#a)",
+        ] {
+            assert!(!super::is_singular(selector), "{}", selector);
+        }
+    }
+}
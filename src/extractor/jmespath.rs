@@ -0,0 +1,64 @@
+use crate::response::Response;
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+
+/*
+  JMESPath queries over the body, for when a path isn't enough and the value
+  needs working out, e.g. `max(items[].id)`, `length(items)` or
+  `join(', ', items[].name)`.
+*/
+
+pub fn query(expression: &str, value: Value) -> Result<Value> {
+    let compiled = ::jmespath::compile(expression)
+        .map_err(|e| anyhow!("Invalid JMESPath [{}]: {}", expression, e))?;
+    let result = compiled
+        .search(value)
+        .map_err(|e| anyhow!("JMESPath [{}] failed: {}", expression, e))?;
+
+    match serde_json::to_value(&*result)? {
+        Value::Null => Err(anyhow!("JMESPath [{}] found nothing", expression)),
+        value => Ok(value),
+    }
+}
+
+pub fn extract(extract_string: &str, response: &Response) -> Result<Value> {
+    println!("JMESPath Parsing [{}]", extract_string);
+    let body = response
+        .text()
+        .ok_or_else(|| anyhow!("Body is binary, not JSON"))?;
+    let v: Value = serde_json::from_str(&body).with_context(|| "Body isn't valid JSON")?;
+
+    query(extract_string, v)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::response::ResponseBuilder;
+    use serde_json::json;
+
+    #[test]
+    fn transforms() {
+        let response = ResponseBuilder::new()
+            .body(
+                br#"{ "items": [
+                    { "id": 4, "name": "four", "active": true },
+                    { "id": 9, "name": "nine", "active": false },
+                    { "id": 5, "name": "five", "active": true }
+                ] }"#
+                    .to_vec(),
+            )
+            .build();
+        let extract = |query: &str| super::extract(query, &response).unwrap();
+
+        assert_eq!(extract("max(items[].id)"), 9);
+        assert_eq!(extract("length(items)"), 3);
+        assert_eq!(extract("join(', ', items[?active].name)"), "four, five");
+        assert_eq!(extract("max_by(items, &id).name"), "nine");
+        assert_eq!(
+            extract("items[0]"),
+            json!({ "active": true, "id": 4, "name": "four" })
+        );
+        assert!(super::extract("items[0].missing", &response).is_err());
+        assert!(super::extract("max(", &response).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+use crate::response::Response;
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use serde_json_path::JsonPath;
+
+/*
+  JSONPath (RFC 9535) queries against the body. The leading `$` is optional,
+  so the older `some.value` and `list[1]` forms still work as before. A
+  singular query, made only of names and indexes, gives the value it finds.
+  Anything else (wildcards, slices, filters, `..`) always gives an array of
+  the matches, so the shape doesn't depend on how many there happen to be.
+*/
+
+fn normalise(path: &str) -> String {
+    if path.starts_with('$') {
+        path.to_owned()
+    } else if path.starts_with('[') || path.starts_with("..") {
+        format!("${}", path)
+    } else {
+        format!("$.{}", path)
+    }
+}
+
+// Comparisons in filters only take singular queries, so the parser can tell us
+fn is_singular(normalised: &str) -> bool {
+    JsonPath::parse(&format!("$[?{} == null]", normalised)).is_ok()
+}
+
+pub fn query(path: &str, value: &Value) -> Result<Value> {
+    let normalised = normalise(path);
+    let json_path = JsonPath::parse(&normalised)
+        .with_context(|| format!("Invalid JSONPath [{}]", normalised))?;
+
+    let nodes = json_path.query(value);
+    if !is_singular(&normalised) {
+        return Ok(Value::Array(nodes.into_iter().cloned().collect()));
+    }
+    nodes
+        .exactly_one()
+        .cloned()
+        .map_err(|_| anyhow!("Couldn't find [{}] in the response body", path))
+}
+
+// JSON pointers to every node the path matches, for editing them in place
+pub fn locate(path: &str, value: &Value) -> Result<Vec<String>> {
+    let normalised = normalise(path);
+    let json_path = JsonPath::parse(&normalised)
+        .with_context(|| format!("Invalid JSONPath [{}]", normalised))?;
+
+    Ok(json_path
+        .query_located(value)
+        .locations()
+        .map(|location| location.to_json_pointer())
+        .collect())
+}
+
+pub fn extract(extract_string: &str, response: &Response) -> Result<Value> {
+    println!("JSON Parsing [{}]", extract_string);
+    let body = response
+        .text()
+        .ok_or_else(|| anyhow!("Body is binary, not JSON"))?;
+    let v: Value = serde_json::from_str(body.as_str()).with_context(|| "Body isn't valid JSON")?;
+
+    query(extract_string, &v)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::response::ResponseBuilder;
+    use serde_json::json;
+
+    fn get_basic_string() -> &'static str {
+        r#"
+        {
+            "name": "John Doe",
+            "age": 43,
+            "phones": [
+                "+44 1234567",
+                "+44 2345678"
+            ]
+        }"#
+    }
+
+    #[test]
+    fn basic_string() {
+        let response = ResponseBuilder::new()
+            .body(get_basic_string().as_bytes().to_vec())
+            .build();
+        let to_extract = "name";
+        let value = super::extract(to_extract, &response);
+        assert!(value.is_ok(), "Extracting failed: {:?}", value.unwrap_err());
+        assert_eq!(value.unwrap(), "John Doe");
+    }
+
+    #[test]
+    fn basic_int() {
+        let response = ResponseBuilder::new()
+            .body(get_basic_string().as_bytes().to_vec())
+            .build();
+        let to_extract = "age";
+        let value = super::extract(to_extract, &response);
+        assert!(value.is_ok(), "Extracting failed: {:?}", value.unwrap_err());
+        assert_eq!(value.unwrap(), 43);
+    }
+
+    #[test]
+    fn basic_index() {
+        let response = ResponseBuilder::new()
+            .body(get_basic_string().as_bytes().to_vec())
+            .build();
+        let to_extract = "phones[1]";
+        let value = super::extract(to_extract, &response);
+        assert!(value.is_ok(), "Extracting failed: {:?}", value.unwrap_err());
+        assert_eq!(value.unwrap(), "+44 2345678");
+    }
+
+    fn get_deep_object() -> &'static str {
+        r#"
+        {
+            "foo": {
+                "bar": {
+                    "baz": {
+                        "a": {
+                            "b": {
+                                "c": {
+                                    "d": 1066
+                                },
+                                "x": 6
+                            },
+                            "y": 5
+                        },
+                        "z": 4
+                    },
+                    "zab": 3
+                },
+                "rab": 2
+            },
+            "oof": 1
+        }"#
+    }
+
+    #[test]
+    fn deep_object() {
+        let response = ResponseBuilder::new()
+            .body(get_deep_object().as_bytes().to_vec())
+            .build();
+        let to_extract = "foo.bar.baz.a.b.c.d";
+        let value = super::extract(to_extract, &response);
+        assert!(value.is_ok(), "Extracting failed: {:?}", value.unwrap_err());
+        assert_eq!(value.unwrap(), 1066);
+    }
+
+    fn get_items() -> &'static str {
+        r#"
+        {
+            "items": [
+                { "id": 4, "name": "four", "tags": ["a", "b"] },
+                { "id": 5, "name": "five", "tags": ["c"] }
+            ],
+            "grid": [[1, 2], [3, 4]],
+            "user_2": { "first-name": "Ann", "a.b": true }
+        }"#
+    }
+
+    #[test]
+    fn json_path() {
+        let response = ResponseBuilder::new()
+            .body(get_items().as_bytes().to_vec())
+            .build();
+        let extract = |path: &str| super::extract(path, &response).unwrap();
+
+        assert_eq!(extract("$.items[-1].id"), 5);
+        assert_eq!(extract("grid[1][0]"), 3);
+        assert_eq!(extract("$.grid[0:2][1]"), json!([2, 4]));
+        assert_eq!(extract("user_2['first-name']"), "Ann");
+        assert_eq!(extract("user_2['a.b']"), true);
+        assert_eq!(extract("items[*].tags[0]"), json!(["a", "c"]));
+        assert_eq!(extract("..name"), json!(["four", "five"]));
+        assert!(super::extract("items[2].name", &response).is_err());
+        assert!(super::extract("items[", &response).is_err());
+    }
+
+    #[test]
+    fn non_singular_queries_give_arrays() {
+        let response = ResponseBuilder::new()
+            .body(get_items().as_bytes().to_vec())
+            .build();
+        let extract = |path: &str| super::extract(path, &response).unwrap();
+
+        // However many nodes match, even one or none
+        assert_eq!(extract("items[?(@.id==5)].name"), json!(["five"]));
+        assert_eq!(extract("items[?(@.id==6)].name"), json!([]));
+        assert_eq!(extract("items[1:].id"), json!([5]));
+        assert_eq!(extract("items[1].tags[*]"), json!(["c"]));
+        assert_eq!(extract("user_2..missing"), json!([]));
+        assert_eq!(extract("$..['first-name']"), json!(["Ann"]));
+        assert_eq!(extract("items[0]['id','name']"), json!([4, "four"]));
+    }
+
+    #[test]
+    fn root_array() {
+        let response = ResponseBuilder::new()
+            .body(br#"[{ "id": 1 }, { "id": 2 }]"#.to_vec())
+            .build();
+        assert_eq!(super::extract("[1].id", &response).unwrap(), 2);
+        assert_eq!(
+            super::extract("$[0]", &response).unwrap(),
+            json!({ "id": 1 })
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use crate::common::{self, property_text, PropertyMap};
+use crate::response::Response;
+use anyhow::{anyhow, Context, Result};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+
+/*
+  Decodes a JWT found elsewhere and reads from it, as `jwt:<source>#<path>`.
+  The source is any other extractor string, e.g. `json:access_token` or
+  `header:Authorization`, or `property:<name>` for a property extracted earlier.
+
+  The path reads from the claims, or from the JOSE header with `header.alg`.
+  `expired` and `expires_in` look at the `exp` claim. Nothing here checks the
+  signature, that's done by a `jwt` assertion with the secret or public key.
+*/
+
+// Finds the token a `jwt:` source points at, dropping any `Bearer ` in front
+pub fn token(source: &str, response: &Response, properties: &PropertyMap) -> Result<String> {
+    let value = match source.strip_prefix("property:") {
+        Some(name) => properties
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("No property [{}] to read a JWT from", name))?,
+        None => super::extract_value(source, response, properties)?,
+    };
+    let value = property_text(&value);
+    let token = value.trim();
+    Ok(token
+        .strip_prefix("Bearer ")
+        .unwrap_or(token)
+        .trim()
+        .to_owned())
+}
+
+fn decode_part(part: Option<&str>, name: &str) -> Result<Value> {
+    let part = part.ok_or_else(|| anyhow!("Value isn't a JWT"))?;
+    let bytes = super::transform::base64_decode(part)
+        .with_context(|| format!("JWT {} isn't base64", name))?;
+    serde_json::from_slice(&bytes).with_context(|| format!("JWT {} isn't JSON", name))
+}
+
+// The token's header and claims, without checking the signature
+pub fn decode(token: &str) -> Result<(Value, Value)> {
+    let mut parts = token.split('.');
+    let header = decode_part(parts.next(), "header")?;
+    let claims = decode_part(parts.next(), "claims")?;
+    Ok((header, claims))
+}
+
+fn expires_in(claims: &Value) -> Result<i64> {
+    let exp = claims
+        .get("exp")
+        .and_then(|exp| exp.as_i64())
+        .ok_or_else(|| anyhow!("JWT has no numeric exp claim"))?;
+    Ok(exp - common::now() as i64)
+}
+
+pub fn extract(
+    extract_string: &str,
+    response: &Response,
+    properties: &PropertyMap,
+) -> Result<Value> {
+    println!("JWT Parsing [{}]", extract_string);
+    let (source, path) = extract_string
+        .rsplit_once('#')
+        .ok_or_else(|| anyhow!("Expected jwt:<source>#<path>"))?;
+    let (header, claims) = decode(&token(source, response, properties)?)?;
+
+    match path {
+        "" | "claims" => Ok(claims),
+        "header" => Ok(header),
+        "expired" => match claims.get("exp") {
+            Some(_) => Ok(Value::Bool(expires_in(&claims)? <= 0)),
+            None => Ok(Value::Bool(false)),
+        },
+        "expires_in" => Ok(Value::from(expires_in(&claims)?)),
+        _ => match (path.strip_prefix("header."), path.strip_prefix("claims.")) {
+            (Some(path), _) => super::json::query(path, &header),
+            (_, Some(path)) => super::json::query(path, &claims),
+            _ => super::json::query(path, &claims),
+        },
+    }
+}
+
+/*
+  Checks a token's signature with either an HMAC secret or a PEM public key.
+  The algorithm comes from the token, but has to suit the key that was given,
+  so a token can't pick HMAC and use a public key as its secret.
+*/
+pub fn verify(
+    token: &str,
+    secret: Option<&str>,
+    public_key: Option<&str>,
+    check_expiry: bool,
+    leeway: u64,
+) -> Result<Value> {
+    let header = jsonwebtoken::decode_header(token).with_context(|| "Invalid JWT header")?;
+    let key = match (header.alg, secret, public_key) {
+        (Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512, Some(secret), _) => {
+            DecodingKey::from_secret(secret.as_bytes())
+        }
+        (
+            Algorithm::RS256
+            | Algorithm::RS384
+            | Algorithm::RS512
+            | Algorithm::PS256
+            | Algorithm::PS384
+            | Algorithm::PS512,
+            _,
+            Some(pem),
+        ) => DecodingKey::from_rsa_pem(pem.as_bytes())?,
+        (Algorithm::ES256 | Algorithm::ES384, _, Some(pem)) => {
+            DecodingKey::from_ec_pem(pem.as_bytes())?
+        }
+        (Algorithm::EdDSA, _, Some(pem)) => DecodingKey::from_ed_pem(pem.as_bytes())?,
+        (alg, _, _) => {
+            return Err(anyhow!(
+                "No key given that can verify a {:?} signed JWT",
+                alg
+            ))
+        }
+    };
+
+    let mut validation = Validation::new(header.alg);
+    validation.validate_aud = false;
+    validation.validate_exp = check_expiry;
+    validation.leeway = leeway;
+    if !check_expiry {
+        validation.required_spec_claims.clear();
+    }
+    let data = jsonwebtoken::decode::<Value>(token, &key, &validation)
+        .with_context(|| "JWT failed verification")?;
+    Ok(data.claims)
+}
+
+#[cfg(test)]
+pub mod test {
+    use crate::common::{self, PropertyMap};
+    use crate::response::ResponseBuilder;
+    use jsonwebtoken::{EncodingKey, Header};
+    use serde_json::{json, Value};
+
+    pub fn token(claims: Value, secret: &str) -> String {
+        jsonwebtoken::encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn claims_and_header() {
+        let exp = common::now() + 600;
+        let jwt = token(
+            json!({ "sub": "abc", "exp": exp, "roles": ["a", "b"] }),
+            "s",
+        );
+        let body = json!({ "access_token": jwt }).to_string();
+        let response = ResponseBuilder::new().body(body.into_bytes()).build();
+        let properties = PropertyMap::new();
+        let extract = |view: &str| super::extract(view, &response, &properties).unwrap();
+
+        assert_eq!(extract("json:access_token#sub"), "abc");
+        assert_eq!(extract("json:access_token#claims.roles[1]"), "b");
+        assert_eq!(extract("json:access_token#header.alg"), "HS256");
+        assert_eq!(extract("json:access_token#expired"), false);
+        assert!(extract("json:access_token#expires_in").as_i64().unwrap() > 590);
+        assert_eq!(extract("json:access_token#")["sub"], "abc");
+        assert!(super::extract("json:access_token#missing", &response, &properties).is_err());
+        assert!(super::extract("json:access_token", &response, &properties).is_err());
+    }
+
+    #[test]
+    fn from_property_and_header() {
+        let jwt = token(json!({ "sub": "abc", "exp": 1000 }), "s");
+        let mut headers = std::collections::HashMap::new();
+        headers.insert("authorization".to_owned(), format!("Bearer {}", jwt));
+        let response = ResponseBuilder::new().headers(headers).build();
+        let mut properties = PropertyMap::new();
+        properties.insert("token".to_owned(), jwt.clone().into());
+
+        let value = super::extract("property:token#expired", &response, &properties);
+        assert_eq!(value.unwrap(), true);
+        let value = super::extract("header:Authorization#sub", &response, &properties);
+        assert_eq!(value.unwrap(), "abc");
+        assert!(super::extract("property:other#sub", &response, &properties).is_err());
+    }
+
+    #[test]
+    fn verification() {
+        let exp = common::now() + 600;
+        let jwt = token(json!({ "sub": "abc", "exp": exp, "aud": "x" }), "secret");
+        let claims = super::verify(&jwt, Some("secret"), None, true, 0).unwrap();
+        assert_eq!(claims["sub"], "abc");
+        assert!(super::verify(&jwt, Some("wrong"), None, true, 0).is_err());
+        // An HMAC token can't be checked against a public key
+        assert!(super::verify(&jwt, None, Some("-----BEGIN PUBLIC KEY-----"), true, 0).is_err());
+
+        let expired = token(json!({ "sub": "abc", "exp": 1000 }), "secret");
+        assert!(super::verify(&expired, Some("secret"), None, true, 0).is_err());
+        assert!(super::verify(&expired, Some("secret"), None, false, 0).is_ok());
+        let no_exp = token(json!({ "sub": "abc" }), "secret");
+        assert!(super::verify(&no_exp, Some("secret"), None, false, 0).is_ok());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::response::Response;
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::StatusCode;
+use serde_json::Value;
+use url::Url;
+
+/*
+  Facts about the response rather than its content: `status:` (or
+  `status:reason`), `elapsed_ms:` for the total time (or one phase, e.g.
+  `elapsed_ms:ttfb`), `size:` of the body in bytes, the final `url:` (or one
+  part, e.g. `url:path`) and the whole `body:` as text, or `body:base64`.
+*/
+
+pub fn status(view: &str, response: &Response) -> Result<Value> {
+    match view {
+        "" | "code" => Ok(Value::from(response.status())),
+        "reason" => Ok(Value::from(
+            StatusCode::from_u16(response.status())
+                .ok()
+                .and_then(|status| status.canonical_reason())
+                .unwrap_or_default(),
+        )),
+        _ => Err(anyhow!("Unknown status field [{}]", view)),
+    }
+}
+
+pub fn elapsed(view: &str, response: &Response) -> Result<Value> {
+    let timings = response.timings();
+    let duration = match view {
+        "" | "total" => timings.total,
+        "dns" => timings.dns,
+        "connect" => timings.connect,
+        "tls" => timings.tls,
+        "ttfb" => timings.ttfb,
+        "transfer" => timings.transfer,
+        "decompress" => timings.decompress,
+        _ => return Err(anyhow!("Unknown timing [{}]", view)),
+    };
+    Ok(Value::from(duration.as_millis() as u64))
+}
+
+pub fn size(view: &str, response: &Response) -> Result<Value> {
+    match view {
+        "" | "body" => Ok(Value::from(response.body().len())),
+        _ => Err(anyhow!("Unknown size [{}]", view)),
+    }
+}
+
+pub fn url(view: &str, response: &Response) -> Result<String> {
+    if view.is_empty() {
+        return Ok(response.url().clone());
+    }
+
+    let url = Url::parse(response.url())
+        .with_context(|| format!("Failed to parse [{}] as a URL", response.url()))?;
+    let part = match view {
+        "scheme" => Some(url.scheme().to_owned()),
+        "host" => url.host_str().map(|host| host.to_owned()),
+        "port" => url.port_or_known_default().map(|port| port.to_string()),
+        "path" => Some(url.path().to_owned()),
+        "query" => url.query().map(|query| query.to_owned()),
+        "fragment" => url.fragment().map(|fragment| fragment.to_owned()),
+        _ => return Err(anyhow!("Unknown URL part [{}]", view)),
+    };
+    part.ok_or_else(|| anyhow!("[{}] has no {}", url, view))
+}
+
+pub fn body(view: &str, response: &Response) -> Result<String> {
+    match view {
+        "" | "text" => response
+            .text()
+            .ok_or_else(|| anyhow!("Body is binary, use body:base64")),
+        "base64" => Ok(STANDARD.encode(response.body())),
+        _ => Err(anyhow!("Unknown body format [{}]", view)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::response::{ResponseBuilder, Timings};
+    use std::time::Duration;
+
+    #[test]
+    fn metadata() {
+        let response = ResponseBuilder::new()
+            .status(404)
+            .body(b"gone".to_vec())
+            .url("https://example.com:8443/a/b?x=1".to_owned())
+            .timings(Timings {
+                ttfb: Duration::from_millis(12),
+                total: Duration::from_millis(40),
+                ..Default::default()
+            })
+            .build();
+
+        assert_eq!(super::status("", &response).unwrap(), 404);
+        assert_eq!(super::status("reason", &response).unwrap(), "Not Found");
+        assert_eq!(super::elapsed("", &response).unwrap(), 40);
+        assert_eq!(super::elapsed("ttfb", &response).unwrap(), 12);
+        assert_eq!(super::size("", &response).unwrap(), 4);
+        assert_eq!(
+            super::url("", &response).unwrap(),
+            "https://example.com:8443/a/b?x=1"
+        );
+        assert_eq!(super::url("port", &response).unwrap(), "8443");
+        assert_eq!(super::url("path", &response).unwrap(), "/a/b");
+        assert!(super::url("fragment", &response).is_err());
+        assert_eq!(super::body("", &response).unwrap(), "gone");
+        assert_eq!(super::body("base64", &response).unwrap(), "Z29uZQ==");
+        assert!(super::elapsed("lunch", &response).is_err());
+    }
+
+    #[test]
+    fn binary_body() {
+        let response = ResponseBuilder::new().body(vec![0, 159, 146, 150]).build();
+        assert!(super::body("text", &response).is_err());
+        assert_eq!(super::body("base64", &response).unwrap(), "AJ+Slg==");
+    }
+}
@@ -0,0 +1,167 @@
+use crate::response::Response;
+use ::regex::RegexBuilder;
+use anyhow::{anyhow, Context, Result};
+
+/*
+  Regex matches against the body, or a header with `header:<name>:`, e.g.
+  `regex:/token=(\w+)/i` or `regex:header:Location:/code=(?P<code>\w+)/#code`.
+  The pattern goes between slashes, followed by any flags (`i` ignore case,
+  `m` multi-line, `s` dot matches newlines, `x` verbose) and `#` with the
+  group to extract, by name or number. Without a group the first capture is
+  used, or the whole match if there are none. Anything that doesn't end in a
+  slash and valid flags is a bare pattern with no flags, so paths such as
+  `regex:/api/v\d+` work too.
+*/
+
+struct Query<'a> {
+    pattern: &'a str,
+    flags: &'a str,
+    group: Option<&'a str>,
+}
+
+// Whatever follows the closing slash: flags, then optionally `#` and a group
+fn is_suffix(suffix: &str) -> bool {
+    let (flags, group) = suffix.split_once('#').unwrap_or((suffix, "a"));
+    flags.chars().all(|flag| "imsx".contains(flag))
+        && !group.is_empty()
+        && group.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn parse(query: &str) -> Query<'_> {
+    let delimited = query
+        .strip_prefix('/')
+        .and_then(|rest| rest.rfind('/').map(|end| (rest, end)))
+        .filter(|(rest, end)| is_suffix(&rest[end + 1..]));
+    let Some((delimited, end)) = delimited else {
+        return Query {
+            pattern: query,
+            flags: "",
+            group: None,
+        };
+    };
+
+    let (flags, group) = match delimited[end + 1..].split_once('#') {
+        Some((flags, group)) => (flags, Some(group)),
+        None => (&delimited[end + 1..], None),
+    };
+    Query {
+        pattern: &delimited[..end],
+        flags,
+        group,
+    }
+}
+
+fn find(query: &str, text: &str) -> Result<String> {
+    let Query {
+        pattern,
+        flags,
+        group,
+    } = parse(query);
+
+    let mut builder = RegexBuilder::new(pattern);
+    for flag in flags.chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            other => return Err(anyhow!("Unknown regex flag [{}]", other)),
+        };
+    }
+    let re = builder
+        .build()
+        .with_context(|| format!("Invalid regex [{}]", pattern))?;
+
+    let captures = re
+        .captures(text)
+        .ok_or_else(|| anyhow!("[{}] didn't match", pattern))?;
+    let matched = match group {
+        Some(group) => match group.parse::<usize>() {
+            Ok(index) => captures.get(index),
+            Err(_) => captures.name(group),
+        },
+        None => captures.get(1).or_else(|| captures.get(0)),
+    };
+    matched
+        .map(|m| m.as_str().to_owned())
+        .ok_or_else(|| anyhow!("[{}] has no group [{}]", pattern, group.unwrap_or("1")))
+}
+
+pub fn extract(extract_string: &str, response: &Response) -> Result<String> {
+    println!("Regex Parsing [{}]", extract_string);
+
+    if let Some(rest) = extract_string.strip_prefix("header:") {
+        let (name, query) = rest
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected header:<name>:<pattern>, got [{}]", rest))?;
+        let value = response
+            .headers()
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+            .ok_or_else(|| anyhow!("Could not find header [{}]", name))?;
+        return find(query, value);
+    }
+
+    let body = response
+        .text()
+        .ok_or_else(|| anyhow!("Body is binary, not text"))?;
+    find(extract_string, &body)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::response::ResponseBuilder;
+    use std::collections::HashMap;
+
+    #[test]
+    fn body() {
+        let response = ResponseBuilder::new()
+            .body(b"<input name=\"csrf\" value=\"abc123\">\nTotal: 42\nTOKEN=xyz".to_vec())
+            .build();
+        let extract = |query: &str| super::extract(query, &response).unwrap();
+
+        assert_eq!(extract(r#"value="(\w+)""#), "abc123");
+        assert_eq!(extract(r"Total: \d+"), "Total: 42");
+        assert_eq!(extract(r"/token=(?P<token>\w+)/i#token"), "xyz");
+        assert_eq!(extract(r"/^Total: (\d+)$/m"), "42");
+        assert_eq!(extract(r#"/(\w+)="(\w+)"/#2"#), "csrf");
+        assert_eq!(extract(r"/input.*Total/s#0").len(), 39);
+        assert!(super::extract(r"/token=(\w+)/", &response).is_err());
+        assert!(super::extract(r"/(\w+)/#3", &response).is_err());
+        assert!(super::extract(r"/x/q", &response).is_err());
+    }
+
+    #[test]
+    fn bare_patterns_starting_with_a_slash() {
+        let response = ResponseBuilder::new()
+            .body(b"GET /api/v2/items/7 HTTP/1.1".to_vec())
+            .build();
+        let extract = |query: &str| super::extract(query, &response).unwrap();
+
+        assert_eq!(extract(r"/api/v\d+"), "/api/v2");
+        assert_eq!(extract(r"/api/v(\d+)/items/(\d+)"), "2");
+        assert_eq!(extract(r"/items/(\d+)/#1"), "7");
+        assert_eq!(extract(r"/ITEMS/i"), "items");
+    }
+
+    #[test]
+    fn header() {
+        let mut headers = HashMap::new();
+        headers.insert(
+            "location".to_owned(),
+            "https://example.com/cb?code=a1b2&state=s".to_owned(),
+        );
+        let response = ResponseBuilder::new().headers(headers).build();
+
+        assert_eq!(
+            super::extract(r"header:Location:/code=(?P<code>\w+)/#code", &response).unwrap(),
+            "a1b2"
+        );
+        assert_eq!(
+            super::extract(r"header:location:state=(\w+)", &response).unwrap(),
+            "s"
+        );
+        assert!(super::extract(r"header:Missing:(\w+)", &response).is_err());
+    }
+}
@@ -0,0 +1,64 @@
+use crate::common::PropertyMap;
+use crate::response::{Response, ResponseBuilder};
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+/*
+  Reads from the request that was sent rather than the response, so values
+  generated while building it, like `{$uuid}`, can be kept as properties.
+  `request.body:<extractor>` runs any body extractor over the sent body,
+  `request.header:<name>` reads a sent header, `request.uri` (or a part
+  such as `request.uri:path`) the URI and `request.method` the method.
+*/
+
+pub fn extract(view: &str, response: &Response, properties: &PropertyMap) -> Result<Value> {
+    println!("Request Parsing [{}]", view);
+    let request = response.request();
+    let sent = ResponseBuilder::new()
+        .headers(request.headers.clone())
+        .body(request.body.clone())
+        .url(request.uri.clone())
+        .build();
+
+    let (source, rest) = view.split_once(':').unwrap_or((view, ""));
+    match source {
+        "body" if rest.is_empty() => super::extract_value("body:", &sent, properties),
+        "body" => super::extract_value(rest, &sent, properties),
+        "header" => super::extract_value(view, &sent, properties),
+        "uri" => super::extract_value(&format!("url:{}", rest), &sent, properties),
+        "method" => Ok(Value::from(request.method.as_str())),
+        _ => Err(anyhow!("Unknown request source [{}]", source)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::common::{PropertyMap, StringMap};
+    use crate::response::{ResponseBuilder, SentRequest};
+
+    #[test]
+    fn sent_request() {
+        let mut headers = StringMap::new();
+        headers.insert("x-request-id".to_owned(), "abc-123".to_owned());
+        let response = ResponseBuilder::new()
+            .body(br#"{ "id": "response" }"#.to_vec())
+            .request(SentRequest {
+                method: "POST".to_owned(),
+                uri: "http://localhost/orders?ref=7".to_owned(),
+                headers,
+                body: br#"{ "id": "sent", "items": [1, 2] }"#.to_vec(),
+            })
+            .build();
+        let properties = PropertyMap::new();
+        let extract = |view: &str| super::extract(view, &response, &properties);
+
+        assert_eq!(extract("body:json:id").unwrap(), "sent");
+        assert_eq!(extract("body:json:items[1]").unwrap(), 2);
+        assert_eq!(extract("header:X-Request-Id").unwrap(), "abc-123");
+        assert_eq!(extract("uri").unwrap(), "http://localhost/orders?ref=7");
+        assert_eq!(extract("uri:path").unwrap(), "/orders");
+        assert_eq!(extract("method").unwrap(), "POST");
+        assert!(extract("body:json:missing").is_err());
+        assert!(extract("status").is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use crate::common::property_text;
+use anyhow::{anyhow, Context, Result};
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
+use serde_json::Value;
+use std::fmt::Write;
+
+/*
+  Transforms piped onto the end of an extractor string, e.g.
+  `header:Authorization | trim_prefix("Bearer ") | jwt_claim("sub")`.
+  Arguments are JSON literals, so strings are double quoted and numbers bare.
+*/
+
+#[derive(Debug, PartialEq)]
+pub struct Transform {
+    name: String,
+    args: Vec<Value>,
+}
+
+const NAMES: &[&str] = &[
+    "trim",
+    "trim_prefix",
+    "trim_suffix",
+    "lowercase",
+    "uppercase",
+    "substring",
+    "base64_decode",
+    "jwt_claim",
+    "to_number",
+    "format_date",
+];
+
+// Splits on `sep` wherever it isn't inside a string literal or brackets
+fn split_outside(text: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quoted, mut escaped) = (0, false, false);
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => depth -= 1,
+            _ if c == sep && !quoted && depth == 0 => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+fn parse_transform(text: &str) -> Option<Transform> {
+    let text = text.trim();
+    let (name, args) = match text.split_once('(') {
+        Some((name, rest)) => (name.trim(), Some(rest.strip_suffix(')')?)),
+        None => (text, None),
+    };
+    if !NAMES.contains(&name) {
+        return None;
+    }
+
+    let args = match args {
+        Some(args) if !args.trim().is_empty() => split_outside(args, ',')
+            .into_iter()
+            .map(|arg| serde_json::from_str(arg.trim()).ok())
+            .collect::<Option<Vec<Value>>>()?,
+        _ => Vec::new(),
+    };
+    Some(Transform {
+        name: name.to_owned(),
+        args,
+    })
+}
+
+/*
+  Splits an extractor string into the extractor and its transforms. Only a tail
+  of `|` separated parts that all read as transforms is taken, so a `|` in a
+  regex or JSONPath filter stays with the extractor.
+*/
+pub fn split(extract: &str) -> (&str, Vec<Transform>) {
+    let parts = split_outside(extract, '|');
+    for first in 1..parts.len() {
+        let transforms: Option<Vec<Transform>> = parts[first..]
+            .iter()
+            .map(|part| parse_transform(part))
+            .collect();
+        if let Some(transforms) = transforms {
+            let end = parts[..first]
+                .iter()
+                .map(|part| part.len() + 1)
+                .sum::<usize>()
+                - 1;
+            return (extract[..end].trim_end(), transforms);
+        }
+    }
+    (extract, Vec::new())
+}
+
+impl Transform {
+    fn string_arg(&self, idx: usize) -> Result<&str> {
+        self.args
+            .get(idx)
+            .and_then(|arg| arg.as_str())
+            .ok_or_else(|| anyhow!("{} needs a string as argument {}", self.name, idx + 1))
+    }
+
+    fn apply(&self, value: Value) -> Result<Value> {
+        let text = property_text(&value);
+        match self.name.as_str() {
+            "trim" => Ok(text.trim().into()),
+            "trim_prefix" => {
+                let prefix = self.string_arg(0)?;
+                Ok(text.strip_prefix(prefix).unwrap_or(&text).into())
+            }
+            "trim_suffix" => {
+                let suffix = self.string_arg(0)?;
+                Ok(text.strip_suffix(suffix).unwrap_or(&text).into())
+            }
+            "lowercase" => Ok(text.to_lowercase().into()),
+            "uppercase" => Ok(text.to_uppercase().into()),
+            "substring" => substring(&text, &self.args),
+            "base64_decode" => {
+                let decoded = base64_decode(&text)?;
+                Ok(String::from_utf8(decoded)
+                    .with_context(|| "Decoded base64 isn't UTF-8")?
+                    .into())
+            }
+            "jwt_claim" => jwt_claim(&text, self.string_arg(0)?),
+            "to_number" => to_number(&value),
+            "format_date" => format_date(&value, self.string_arg(0)?),
+            other => Err(anyhow!("Unknown transform [{}]", other)),
+        }
+    }
+}
+
+pub fn apply(transforms: &[Transform], value: Value) -> Result<Value> {
+    transforms.iter().try_fold(value, |value, transform| {
+        transform
+            .apply(value)
+            .with_context(|| format!("Transform [{}] failed", transform.name))
+    })
+}
+
+// Characters from `start` up to, but not including, `end`. Negative positions count from the end
+fn substring(text: &str, args: &[Value]) -> Result<Value> {
+    let chars: Vec<char> = text.chars().collect();
+    let len = chars.len() as i64;
+    let position = |arg: Option<&Value>, default: i64| -> Result<usize> {
+        let position = match arg {
+            Some(arg) => arg
+                .as_i64()
+                .ok_or_else(|| anyhow!("substring positions must be integers"))?,
+            None => default,
+        };
+        let position = if position < 0 {
+            len + position
+        } else {
+            position
+        };
+        Ok(position.clamp(0, len) as usize)
+    };
+    let start = position(args.first(), 0)?;
+    let end = position(args.get(1), len)?.max(start);
+    Ok(chars[start..end].iter().collect::<String>().into())
+}
+
+// Accepts either alphabet, with or without padding, as JWTs and APIs vary
+pub fn base64_decode(text: &str) -> Result<Vec<u8>> {
+    let config =
+        GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+    let text = text.trim();
+    let engine = if text.contains(['-', '_']) {
+        GeneralPurpose::new(&alphabet::URL_SAFE, config)
+    } else {
+        GeneralPurpose::new(&alphabet::STANDARD, config)
+    };
+    engine
+        .decode(text)
+        .with_context(|| "Value isn't valid base64")
+}
+
+// Reads a claim from a JWT's payload, without checking its signature
+fn jwt_claim(token: &str, claim: &str) -> Result<Value> {
+    let payload = token
+        .trim()
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| anyhow!("Value isn't a JWT"))?;
+    let payload: Value = serde_json::from_slice(&base64_decode(payload)?)
+        .with_context(|| "JWT payload isn't JSON")?;
+    super::json::query(claim, &payload)
+}
+
+fn to_number(value: &Value) -> Result<Value> {
+    if value.is_number() {
+        return Ok(value.clone());
+    }
+    let text = property_text(value);
+    let text = text.trim();
+    if let Ok(int) = text.parse::<i64>() {
+        return Ok(int.into());
+    }
+    text.parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+        .map(Value::Number)
+        .ok_or_else(|| anyhow!("[{}] isn't a number", text))
+}
+
+// Takes unix seconds, RFC 3339 or RFC 2822 dates, and formats them in UTC with strftime syntax
+fn format_date(value: &Value, format: &str) -> Result<Value> {
+    let date: DateTime<Utc> = match value {
+        Value::Number(seconds) => seconds
+            .as_i64()
+            .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single()),
+        Value::String(text) => DateTime::parse_from_rfc3339(text.trim())
+            .or_else(|_| DateTime::parse_from_rfc2822(text.trim()))
+            .ok()
+            .map(|date| date.with_timezone(&Utc))
+            .or_else(|| {
+                let seconds = text.trim().parse::<i64>().ok()?;
+                Utc.timestamp_opt(seconds, 0).single()
+            }),
+        _ => None,
+    }
+    .ok_or_else(|| anyhow!("[{}] isn't a date", property_text(value)))?;
+
+    // An invalid format string only shows up while writing, and `to_string` would panic on it
+    let mut formatted = String::new();
+    write!(formatted, "{}", date.format(format))
+        .map_err(|_| anyhow!("Invalid date format [{}]", format))?;
+    Ok(formatted.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply, split};
+    use serde_json::{json, Value};
+
+    fn run(extract: &str, value: Value) -> Value {
+        let (_, transforms) = split(extract);
+        apply(&transforms, value).unwrap()
+    }
+
+    #[test]
+    fn splitting() {
+        let (extractor, transforms) =
+            split(r#"header:Authorization | trim_prefix("Bearer ") | jwt_claim("sub")"#);
+        assert_eq!(extractor, "header:Authorization");
+        assert_eq!(transforms.len(), 2);
+
+        // A `|` that isn't followed by transforms belongs to the extractor
+        let (extractor, transforms) = split(r"regex:/(a|b)+/ | uppercase");
+        assert_eq!(extractor, "regex:/(a|b)+/");
+        assert_eq!(transforms.len(), 1);
+        assert_eq!(split("regex:/a|b/").0, "regex:/a|b/");
+        assert_eq!(split("regex:/a|lowercase/").1.len(), 0);
+        assert_eq!(split(r#"jmespath:a || b"#).0, "jmespath:a || b");
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            run(
+                r#"x | trim_prefix("Bearer ") | uppercase"#,
+                json!("Bearer abc")
+            ),
+            "ABC"
+        );
+        assert_eq!(run("x | trim | lowercase", json!("  MiXed ")), "mixed");
+        assert_eq!(run(r#"x | trim_suffix(".json")"#, json!("a.json")), "a");
+        assert_eq!(run("x | substring(1, 3)", json!("abcdef")), "bc");
+        assert_eq!(run("x | substring(-2)", json!("abcdef")), "ef");
+        assert_eq!(run("x | substring(4, 99)", json!("abcdef")), "ef");
+        assert_eq!(run("x | base64_decode", json!("aGVsbG8")), "hello");
+    }
+
+    #[test]
+    fn jwt_and_numbers() {
+        // {"alg":"none"}.{"sub":"1234","admin":true,"org":{"id":7}}
+        let token = "eyJhbGciOiJub25lIn0.eyJzdWIiOiIxMjM0IiwiYWRtaW4iOnRydWUsIm9yZyI6eyJpZCI6N319.";
+        assert_eq!(run(r#"x | jwt_claim("sub")"#, json!(token)), "1234");
+        assert_eq!(run(r#"x | jwt_claim("org.id")"#, json!(token)), 7);
+        assert_eq!(
+            run(r#"x | jwt_claim("sub") | to_number"#, json!(token)),
+            1234
+        );
+        assert_eq!(run("x | to_number", json!(" 1.5 ")), 1.5);
+
+        let (_, transforms) = split(r#"x | jwt_claim("missing")"#);
+        assert!(apply(&transforms, json!(token)).is_err());
+        let (_, transforms) = split("x | to_number");
+        assert!(apply(&transforms, json!("abc")).is_err());
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(
+            run(r#"x | format_date("%Y-%m-%d")"#, json!(1700000000)),
+            "2023-11-14"
+        );
+        assert_eq!(
+            run(
+                r#"x | format_date("%d/%m/%Y %H:%M")"#,
+                json!("2024-02-29T23:30:00+02:00")
+            ),
+            "29/02/2024 21:30"
+        );
+        assert_eq!(
+            run(
+                r#"x | format_date("%Y")"#,
+                json!("Tue, 1 Jul 2003 10:52:37 +0200")
+            ),
+            "2003"
+        );
+
+        let (_, transforms) = super::split(r#"x | format_date("%Q")"#);
+        assert!(apply(&transforms, json!(0)).is_err());
+    }
+}
@@ -0,0 +1,260 @@
+use crate::response::Response;
+use anyhow::{anyhow, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde_json::Value;
+use sxd_document::dom::{ChildOfElement, ChildOfRoot, Element};
+use sxd_xpath::{Context, Factory};
+
+/*
+  XPath 1.0 queries against an XML body, e.g. `xpath:/soap:Envelope/soap:Body//m:Price`.
+  Every namespace prefix declared in the document can be used in the query,
+  and elements in a default namespace are reached with the `ns` prefix.
+  A path that can only reach one node, such as `/a/b[1]/@id` or `(//item)[2]`,
+  gives that node's text. Any other node set gives a JSON array of their text,
+  so the shape doesn't depend on how many nodes the document happens to have.
+*/
+
+lazy_static! {
+    static ref LITERAL_RE: Regex =
+        Regex::new(r#"'[^']*'|"[^"]*""#).expect("Failed to create regex for XPath literals");
+    static ref PREFIX_RE: Regex = Regex::new(r"(?:^|[^\w.-])([A-Za-z_][\w.-]*):[A-Za-z_*]")
+        .expect("Failed to create regex for XPath prefixes");
+    static ref ELEMENT_RE: Regex =
+        Regex::new(r"^(child::)?[A-Za-z_*][\w.-]*(:[\w.*-]+)?(\[.*\])*$")
+            .expect("Failed to create regex for XPath elements");
+    static ref ATTRIBUTE_RE: Regex = Regex::new(r"^(@|attribute::)[A-Za-z_][\w.-]*(:[\w.-]+)?$")
+        .expect("Failed to create regex for XPath attributes");
+    static ref POSITION_RE: Regex = Regex::new(r"\[\s*(\d+|last\(\))\s*\]$")
+        .expect("Failed to create regex for XPath positions");
+}
+
+// Prefix for the default namespace, as XPath 1.0 has no way to name it otherwise
+const DEFAULT_PREFIX: &str = "ns";
+
+fn bind_namespaces<'d>(element: Element<'d>, context: &mut Context<'d>, bound: &mut Vec<String>) {
+    let mut bind = |prefix: &str, uri: &str| {
+        if !bound.iter().any(|existing| existing == prefix) {
+            context.set_namespace(prefix, uri);
+            bound.push(prefix.to_owned());
+        }
+    };
+    for namespace in element.namespaces_in_scope() {
+        bind(namespace.prefix(), namespace.uri());
+    }
+    if let Some(uri) = element.default_namespace_uri() {
+        bind(DEFAULT_PREFIX, uri);
+    }
+
+    for child in element.children() {
+        if let ChildOfElement::Element(child) = child {
+            bind_namespaces(child, context, bound);
+        }
+    }
+}
+
+// sxd-xpath panics on prefixes it doesn't know, so catch them first
+fn check_prefixes(xpath: &str, bound: &[String]) -> Result<()> {
+    let without_literals = LITERAL_RE.replace_all(xpath, "''");
+    for captures in PREFIX_RE.captures_iter(&without_literals) {
+        let prefix = &captures[1];
+        if !bound.iter().any(|existing| existing == prefix) {
+            return Err(anyhow!(
+                "Unknown namespace prefix [{}] in [{}]",
+                prefix,
+                xpath
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Splits a path on its top-level slashes, or gives None for a union of paths
+fn split_steps(xpath: &str) -> Option<Vec<&str>> {
+    let mut steps = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (idx, c) in xpath.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            '|' if depth == 0 => return None,
+            '/' if depth == 0 => {
+                steps.push(&xpath[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    steps.push(&xpath[start..]);
+    Some(steps)
+}
+
+/*
+    Whether the path can only reach one node. The document has a single element
+    at the top, and after that each step has to pick one node by position, or
+    be an attribute, `.` or `..`. Anything with `//` can reach any number.
+*/
+fn is_singular(xpath: &str) -> bool {
+    let without_literals = LITERAL_RE.replace_all(xpath.trim(), "''");
+    let Some(mut steps) = split_steps(&without_literals) else {
+        return false;
+    };
+    if steps[0].is_empty() {
+        steps.remove(0);
+    }
+    let Some((first, rest)) = steps.split_first() else {
+        return false;
+    };
+
+    let picks_one = |step: &str| {
+        let step = step.trim();
+        matches!(step, "." | "..") || ATTRIBUTE_RE.is_match(step) || POSITION_RE.is_match(step)
+    };
+    (ELEMENT_RE.is_match(first.trim()) || picks_one(first))
+        && rest.iter().all(|step| picks_one(step))
+}
+
+// Whole numbers come back as integers rather than XPath's floats
+fn number(number: f64) -> Value {
+    if number.fract() == 0.0 && number.is_finite() {
+        Value::from(number as i64)
+    } else {
+        Value::from(number)
+    }
+}
+
+pub fn extract(extract_string: &str, response: &Response) -> Result<Value> {
+    println!("XPath Parsing [{}]", extract_string);
+    let body = response
+        .text()
+        .ok_or_else(|| anyhow!("Body is binary, not XML"))?;
+    let package =
+        sxd_document::parser::parse(&body).map_err(|e| anyhow!("Body isn't valid XML: {:?}", e))?;
+    let document = package.as_document();
+
+    let xpath = Factory::new()
+        .build(extract_string)
+        .map_err(|e| anyhow!("Invalid XPath [{}]: {}", extract_string, e))?
+        .ok_or_else(|| anyhow!("Empty XPath"))?;
+
+    let mut context = Context::new();
+    let mut bound = Vec::new();
+    for child in document.root().children() {
+        if let ChildOfRoot::Element(element) = child {
+            bind_namespaces(element, &mut context, &mut bound);
+        }
+    }
+
+    check_prefixes(extract_string, &bound)?;
+    let value = xpath
+        .evaluate(&context, document.root())
+        .map_err(|e| anyhow!("XPath [{}] failed: {}", extract_string, e))?;
+    match value {
+        sxd_xpath::Value::Boolean(b) => Ok(Value::Bool(b)),
+        sxd_xpath::Value::Number(n) => Ok(number(n)),
+        sxd_xpath::Value::String(s) => Ok(Value::String(s)),
+        sxd_xpath::Value::Nodeset(nodes) => {
+            let values: Vec<Value> = nodes
+                .document_order()
+                .iter()
+                .map(|node| Value::String(node.string_value()))
+                .collect();
+            if !is_singular(extract_string) {
+                return Ok(Value::Array(values));
+            }
+            values
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("[{}] matched nothing", extract_string))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::response::ResponseBuilder;
+    use serde_json::json;
+
+    fn soap_response() -> &'static str {
+        r#"<?xml version="1.0"?>
+        <soap:Envelope xmlns:soap="http://www.w3.org/2003/05/soap-envelope">
+            <soap:Body>
+                <m:GetPriceResponse xmlns:m="https://www.example.org/stock">
+                    <m:Price currency="EUR">34.5</m:Price>
+                    <m:Price currency="SEK">390</m:Price>
+                </m:GetPriceResponse>
+                <Status xmlns="urn:status"><Code>OK</Code></Status>
+            </soap:Body>
+        </soap:Envelope>"#
+    }
+
+    #[test]
+    fn namespaces() {
+        let response = ResponseBuilder::new()
+            .body(soap_response().as_bytes().to_vec())
+            .build();
+        let extract = |query: &str| super::extract(query, &response).unwrap();
+
+        assert_eq!(
+            extract("/soap:Envelope/soap:Body/m:GetPriceResponse/m:Price[@currency='EUR']"),
+            json!(["34.5"])
+        );
+        assert_eq!(extract("(//m:Price)[2]/@currency"), "SEK");
+        assert_eq!(extract("//m:Price"), json!(["34.5", "390"]));
+        assert_eq!(extract("count(//m:Price)"), 2);
+        assert_eq!(extract("//ns:Status/ns:Code"), json!(["OK"]));
+        assert_eq!(extract("//Price"), json!([]));
+        assert!(super::extract("//x:Price", &response).is_err());
+    }
+
+    #[test]
+    fn one_match() {
+        let response = ResponseBuilder::new()
+            .body(br#"<orders><order id="1"><item>Tea</item></order></orders>"#.to_vec())
+            .build();
+        let extract = |query: &str| super::extract(query, &response).unwrap();
+
+        // Only one item in the document, but `//item` could find more in another
+        assert_eq!(extract("//item"), json!(["Tea"]));
+        assert_eq!(extract("/orders/order/item"), json!(["Tea"]));
+        assert_eq!(extract("/orders/order[1]/item[1]"), "Tea");
+        assert_eq!(extract("/orders/order[last()]/@id"), "1");
+        assert_eq!(extract("(//item)[1]"), "Tea");
+        assert_eq!(extract("/orders"), "Tea");
+        assert!(super::extract("/orders/order[2]", &response).is_err());
+    }
+
+    #[test]
+    fn singular_paths() {
+        for xpath in [
+            "/a",
+            "a",
+            "/a/b[1]",
+            "/a/b[2]/@id",
+            "/a/b[1]/..",
+            "(//b)[3]",
+            "/a[@x='a/b|c']/b[last()]",
+        ] {
+            assert!(super::is_singular(xpath), "{}", xpath);
+        }
+        for xpath in [
+            "//a",
+            "/a/b",
+            "/a//b[1]",
+            "/a/b[1]/@*",
+            "/a/b[1] | /a/c[1]",
+            "(//b)",
+            "/a/b[@id='1']",
+            "/a/b[1]/text()",
+        ] {
+            assert!(!super::is_singular(xpath), "{}", xpath);
+        }
+    }
+
+    #[test]
+    fn not_xml() {
+        let response = ResponseBuilder::new().body(b"{}".to_vec()).build();
+        assert!(super::extract("/a", &response).is_err());
+    }
+}
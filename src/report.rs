@@ -0,0 +1,278 @@
+use crate::assertion::Assertion;
+use crate::common::{property_text, PropertyMap};
+use crate::extractor::Extracted;
+use crate::response::Response;
+use crate::schema;
+use crate::snapshot::{Snapshot, Snapshots};
+use anyhow::{anyhow, Result};
+use jsonschema::Validator;
+use std::fmt;
+
+/*
+  Everything the run reports on: what each extraction came to, and which
+  requests passed or failed, so a run can finish with a summary and exit code.
+*/
+
+// Prints each extraction, keeps the values that worked, and fails if a required one didn't
+pub fn extractions(results: Vec<Extracted>, properties: &mut PropertyMap) -> Result<()> {
+    let mut missing = Vec::new();
+    for result in results {
+        match result.value {
+            Ok(value) => {
+                println!(
+                    "Extracted [{}={}] from {}",
+                    result.property,
+                    property_text(&value),
+                    result.from
+                );
+                properties.insert(result.property, value);
+            }
+            Err(e) if result.optional => {
+                println!(
+                    "Optional [{}] not extracted from {}: {:#}",
+                    result.property, result.from, e
+                );
+            }
+            Err(e) => {
+                println!(
+                    "Failed to extract [{}] from {}: {:#}",
+                    result.property, result.from, e
+                );
+                missing.push(result.property);
+            }
+        }
+    }
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Failed to extract required properties [{}]",
+            missing.join(", ")
+        ))
+    }
+}
+
+// Prints how each assertion went, failing if any of them didn't hold
+pub fn assertions(
+    assertions: &[Assertion],
+    response: &Response,
+    properties: &PropertyMap,
+) -> Result<()> {
+    let mut failed = 0;
+    for assertion in assertions {
+        match assertion.check(response, properties) {
+            Ok(_) => println!("Assertion passed [{}]", assertion.describe()),
+            Err(e) => {
+                println!("Assertion failed [{}]: {:#}", assertion.describe(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    match failed {
+        0 => Ok(()),
+        _ => Err(anyhow!(
+            "{} of {} assertion(s) failed",
+            failed,
+            assertions.len()
+        )),
+    }
+}
+
+// Validates the body against the request's schema, printing each violation
+pub fn schema(validator: Option<&Validator>, response: &Response) -> Result<()> {
+    let Some(validator) = validator else {
+        return Ok(());
+    };
+    let violations = schema::violations(validator, response)?;
+    for violation in &violations {
+        println!("Schema violation at {}", violation);
+    }
+
+    match violations.len() {
+        0 => {
+            println!("Body matches schema");
+            Ok(())
+        }
+        count => Err(anyhow!("Body broke the schema {} time(s)", count)),
+    }
+}
+
+// Compares the body with its stored snapshot, printing each difference
+pub fn snapshot(
+    snapshots: &Snapshots,
+    snapshot: Option<&Snapshot>,
+    request_name: Option<&str>,
+    response: &Response,
+) -> Result<()> {
+    let Some(snapshot) = snapshot else {
+        return Ok(());
+    };
+    let differences = snapshots.check(snapshot, request_name, response)?;
+    for difference in &differences {
+        println!("Snapshot difference at {}", difference);
+    }
+
+    match differences.len() {
+        0 => Ok(()),
+        count => Err(anyhow!(
+            "Body differs from its snapshot in {} place(s), rerun with --update-snapshots if that's expected",
+            count
+        )),
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Tally {
+    passed: usize,
+    failed: usize,
+    skipped: usize,
+}
+
+impl fmt::Display for Tally {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} passed, {} failed, {} skipped",
+            self.passed, self.failed, self.skipped
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    total: Tally,
+    // One tally per `--data` row, empty when the run isn't data driven
+    iterations: Vec<Tally>,
+    failures: Vec<(String, String)>,
+    skipped: Vec<(String, String)>,
+}
+
+impl Report {
+    pub fn start_iteration(&mut self) {
+        self.iterations.push(Tally::default());
+        println!("### Iteration {}", self.iterations.len());
+    }
+
+    fn tally(&mut self, count: impl Fn(&mut Tally)) {
+        count(&mut self.total);
+        if let Some(iteration) = self.iterations.last_mut() {
+            count(iteration);
+        }
+    }
+
+    // Labels get the iteration in front, so failures can be traced back to their row
+    fn label(&self, label: &str) -> String {
+        match self.iterations.len() {
+            0 => label.to_owned(),
+            iteration => format!("#{} {}", iteration, label),
+        }
+    }
+
+    pub fn record(&mut self, label: &str, result: &Result<()>) {
+        match result {
+            Ok(_) => self.tally(|tally| tally.passed += 1),
+            Err(e) => {
+                println!("Request [{}] failed: {:#}", label, e);
+                self.tally(|tally| tally.failed += 1);
+                self.failures.push((self.label(label), format!("{:#}", e)));
+            }
+        }
+    }
+
+    pub fn skip(&mut self, label: &str, reason: &str) {
+        println!("Skipping [{}]: {}", label, reason);
+        self.tally(|tally| tally.skipped += 1);
+        self.skipped.push((self.label(label), reason.to_owned()));
+    }
+
+    pub fn failed(&self) -> bool {
+        self.total.failed > 0
+    }
+
+    pub fn print_summary(&self) {
+        println!("===");
+        for (idx, iteration) in self.iterations.iter().enumerate() {
+            println!("Iteration {}: {}", idx + 1, iteration);
+        }
+        println!("{}", self.total);
+        for (label, reason) in &self.failures {
+            println!("  FAILED [{}]: {}", label, reason);
+        }
+        for (label, reason) in &self.skipped {
+            println!("  SKIPPED [{}]: {}", label, reason);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Report;
+    use crate::common::PropertyMap;
+    use crate::extractor::Extracted;
+    use anyhow::anyhow;
+
+    fn extracted(property: &str, optional: bool, worked: bool) -> Extracted {
+        Extracted {
+            property: property.to_owned(),
+            from: format!("json:{}", property),
+            optional,
+            value: match worked {
+                true => Ok(property.into()),
+                false => Err(anyhow!("not there")),
+            },
+        }
+    }
+
+    #[test]
+    fn required_failures_fail() {
+        let mut properties = PropertyMap::new();
+        let results = vec![extracted("a", false, true), extracted("b", true, false)];
+        assert!(super::extractions(results, &mut properties).is_ok());
+        assert_eq!(properties.len(), 1);
+
+        let results = vec![
+            extracted("c", false, false),
+            extracted("d", true, true),
+            extracted("e", false, false),
+        ];
+        let err = super::extractions(results, &mut properties).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Failed to extract required properties [c, e]"
+        );
+        // Whatever did work is still kept
+        assert_eq!(properties["d"], "d");
+
+        let mut report = Report::default();
+        report.record("first", &Ok(()));
+        assert!(!report.failed());
+        report.record("second", &Err(err));
+        assert!(report.failed());
+
+        // Skipping isn't failing
+        let mut report = Report::default();
+        report.skip("third", "`when` was false");
+        assert!(!report.failed());
+        assert_eq!(report.skipped.len(), 1);
+    }
+
+    #[test]
+    fn iterations() {
+        let mut report = Report::default();
+        report.start_iteration();
+        report.record("login", &Ok(()));
+        report.start_iteration();
+        report.record("login", &Err(anyhow!("401")));
+        report.skip("orders", "`when` was false");
+
+        assert!(report.failed());
+        assert_eq!(report.iterations.len(), 2);
+        assert_eq!(report.iterations[0].passed, 1);
+        assert_eq!(report.iterations[1].failed, 1);
+        assert_eq!(report.iterations[1].skipped, 1);
+        assert_eq!(report.total.to_string(), "1 passed, 1 failed, 1 skipped");
+        assert_eq!(report.failures[0].0, "#2 login");
+    }
+}
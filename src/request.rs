@@ -1,18 +1,32 @@
-use crate::common::StringMap;
-use anyhow::{Context, Result};
+use crate::assertion::Assertion;
+use crate::auth::Auth;
+use crate::client::ProxySettings;
+use crate::common::{property_text, PropertyMap, StringMap};
+use crate::condition::Condition;
+use crate::extractor::ExtractMap;
+use crate::schema;
+use crate::signing::Signing;
+use crate::snapshot::Snapshot;
+use anyhow::{anyhow, Context, Result};
+use jsonschema::Validator;
 use lazy_static::lazy_static;
+use rand::Rng;
 use regex::Regex;
-use serde::__private::de::Content;
-use serde::{Deserialize, Deserializer};
+use serde::Deserialize;
+use serde_json::Value;
 use std::fs;
-use std::path::PathBuf;
+use std::path::Path;
+use std::time::Duration;
 
 lazy_static! {
-    static ref RE: Regex = Regex::new(r"\{{1,2}(\w*)\}{1,2}")
+    static ref RE: Regex = Regex::new(r"\{{1,2}(\$?[\w.]*)\}{1,2}")
         .expect("Failed to create regex for Request data replacement");
+    static ref JSON_RE: Regex = Regex::new(r#""\{(\$?[\w.]*)\}""#)
+        .expect("Failed to create regex for JSON body replacement");
 }
 
 // TODO: Expand
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, Deserialize)]
 pub enum Verb {
     GET,
@@ -31,6 +45,137 @@ impl ContentType {
     }
 }
 
+// Compresses the request body, setting Content-Encoding to match
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Deflate,
+    Br,
+}
+
+impl Compression {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::Gzip => "gzip",
+            Compression::Deflate => "deflate",
+            Compression::Br => "br",
+        }
+    }
+}
+
+// What the runner does after a request fails
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnFailure {
+    #[default]
+    Continue,
+    StopCollection,
+    StopRun,
+}
+
+/*
+    Repeat the request until the condition holds, e.g. `json:status == "done"`,
+    or until the timeout is hit.
+*/
+#[derive(Debug, Clone, Deserialize)]
+pub struct Poll {
+    until: String,
+    #[serde(skip)]
+    condition: Option<Condition>,
+    #[serde(default = "Poll::default_interval")]
+    interval_ms: u64,
+    #[serde(default = "Poll::default_timeout")]
+    timeout_ms: u64,
+}
+
+impl Poll {
+    fn default_interval() -> u64 {
+        1000
+    }
+    fn default_timeout() -> u64 {
+        30000
+    }
+
+    pub fn until(&self) -> &str {
+        &self.until
+    }
+    // Parsed once when the collection loads, so a broken condition stops it loading
+    pub fn parse(&mut self) -> Result<()> {
+        let condition = Condition::parse(&self.until)
+            .with_context(|| format!("Failed to parse poll condition [{}]", self.until))?;
+        self.condition = Some(condition);
+        Ok(())
+    }
+    pub fn condition(&self) -> Result<&Condition> {
+        self.condition
+            .as_ref()
+            .ok_or_else(|| anyhow!("Poll condition [{}] hasn't been parsed", self.until))
+    }
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+/*
+    Whether redirects are followed, and how many hops are allowed. Collections carry
+    the same options, which act as defaults for their requests.
+*/
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct RedirectOptions {
+    follow_redirects: Option<bool>,
+    max_redirects: Option<usize>,
+}
+
+impl RedirectOptions {
+    pub fn follow(&self) -> bool {
+        self.follow_redirects.unwrap_or(true)
+    }
+    pub fn max(&self) -> usize {
+        self.max_redirects.unwrap_or(10)
+    }
+
+    // Fill in anything not set on the request from the collection
+    pub fn inherit(&mut self, defaults: &RedirectOptions) {
+        self.follow_redirects = self.follow_redirects.or(defaults.follow_redirects);
+        self.max_redirects = self.max_redirects.or(defaults.max_redirects);
+    }
+}
+
+// A random (version 4) UUID
+fn uuid() -> String {
+    let mut bytes = rand::random::<[u8; 16]>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = hex::encode(bytes);
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/*
+    Values made up while building the request, e.g. `{$uuid}`. Each use gets
+    a new value, so extract it with `request.` extractors to use it again.
+    `$random_int` is evenly spread from 0 to 999.
+*/
+fn generated_value(name: &str) -> Option<Value> {
+    match name {
+        "$uuid" => Some(Value::from(uuid())),
+        "$timestamp" => Some(Value::from(chrono::Utc::now().timestamp())),
+        "$timestamp_ms" => Some(Value::from(chrono::Utc::now().timestamp_millis())),
+        "$random_int" => Some(Value::from(rand::thread_rng().gen_range(0..1000))),
+        _ => None,
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Request {
     // Require properties
@@ -39,42 +184,136 @@ pub struct Request {
 
     // Defaults make these "Optional"
     #[serde(default)]
-    properties: StringMap,
+    name: Option<String>,
+    #[serde(default)]
+    when: Option<String>,
+    #[serde(default)]
+    on_failure: Option<OnFailure>,
+    #[serde(default)]
+    next: Option<String>,
+    #[serde(default)]
+    foreach: Option<String>,
+    #[serde(default)]
+    properties: PropertyMap,
     #[serde(default)]
     headers: StringMap,
     #[serde(default)]
     body: String,
     #[serde(default)]
-    extract: StringMap,
+    extract: ExtractMap,
+    #[serde(default)]
+    assert: Vec<Assertion>,
+    #[serde(default)]
+    schema: Option<Value>,
+    #[serde(skip)]
+    validator: Option<Validator>,
+    #[serde(default)]
+    snapshot: Option<Snapshot>,
     #[serde(default = "ContentType::default")]
     content_type: ContentType,
+    #[serde(default)]
+    compress: Option<Compression>,
+    #[serde(default)]
+    poll: Option<Poll>,
+    #[serde(flatten)]
+    redirects: RedirectOptions,
+    #[serde(default)]
+    proxy: Option<ProxySettings>,
+    #[serde(default)]
+    auth: Option<Auth>,
+    #[serde(default)]
+    sign: Option<Signing>,
 }
 
 impl Request {
     pub fn uri(&self) -> &String {
         &self.uri
     }
+    #[allow(dead_code)]
     pub fn headers(&self) -> &StringMap {
         &self.headers
     }
+    #[allow(dead_code)]
     pub fn body(&self) -> Vec<u8> {
         self.body.as_bytes().to_vec()
     }
     pub fn verb(&self) -> Verb {
         self.verb
     }
-    pub fn extract(&self) -> &StringMap {
+    // What the request is called in the output, its name if it was given one
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{:?} {}", self.verb, self.uri),
+        }
+    }
+    pub fn extract(&self) -> &ExtractMap {
         &self.extract
     }
+    #[allow(dead_code)]
     pub fn content_type(&self) -> ContentType {
         self.content_type
     }
+    pub fn compress(&self) -> Option<Compression> {
+        self.compress
+    }
+    pub fn poll(&self) -> Option<&Poll> {
+        self.poll.as_ref()
+    }
+    pub fn poll_mut(&mut self) -> Option<&mut Poll> {
+        self.poll.as_mut()
+    }
+    pub fn redirects(&self) -> &RedirectOptions {
+        &self.redirects
+    }
+    pub fn redirects_mut(&mut self) -> &mut RedirectOptions {
+        &mut self.redirects
+    }
+    pub fn proxy(&self) -> Option<&ProxySettings> {
+        self.proxy.as_ref()
+    }
+    pub fn auth(&self) -> Option<&Auth> {
+        self.auth.as_ref()
+    }
+    pub fn signing(&self) -> Option<&Signing> {
+        self.sign.as_ref()
+    }
+
+    // Use the collection's auth unless the request has its own
+    pub fn inherit_auth(&mut self, auth: Option<&Auth>) {
+        if self.auth.is_none() {
+            self.auth = auth.cloned();
+        }
+    }
+
+    // Use the collection's signing unless the request has its own
+    pub fn inherit_signing(&mut self, sign: Option<&Signing>) {
+        if self.sign.is_none() {
+            self.sign = sign.cloned();
+        }
+    }
+
+    // Use the collection's failure policy unless the request has its own
+    pub fn inherit_on_failure(&mut self, on_failure: Option<OnFailure>) {
+        self.on_failure = self.on_failure.or(on_failure);
+    }
+
+    // Use the collection's proxy unless the request has its own
+    pub fn inherit_proxy(&mut self, proxy: Option<&ProxySettings>) {
+        if self.proxy.is_none() {
+            self.proxy = proxy.cloned();
+        }
+    }
 
     /*
         Lifetime markers here as we need to say that cached_properties lives just as long,
         or longer, than self.
     */
-    fn get_property<'a>(&'a self, name: &str, cached_properties: &'a StringMap) -> Option<&String> {
+    fn get_property<'a>(
+        &'a self,
+        name: &str,
+        cached_properties: &'a PropertyMap,
+    ) -> Option<&'a Value> {
         match self.properties.get(name) {
             Some(value) => Some(value),
             None => match cached_properties.get(name) {
@@ -84,15 +323,30 @@ impl Request {
         }
     }
 
-    fn replace_text(&self, text: &String, cached_properties: &StringMap) -> String {
+    // The properties the request is sent with, its own taking the place of the run's
+    pub fn merged_properties(&self, cached_properties: &PropertyMap) -> PropertyMap {
+        let mut merged = cached_properties.clone();
+        merged.extend(self.properties.clone());
+        merged
+    }
+
+    // A property, or a value generated fresh each time for names like `$uuid`
+    fn lookup(&self, name: &str, cached_properties: &PropertyMap) -> Option<Value> {
+        match name.starts_with('$') {
+            true => generated_value(name),
+            false => self.get_property(name, cached_properties).cloned(),
+        }
+    }
+
+    fn replace_text(&self, text: &str, cached_properties: &PropertyMap) -> String {
         RE.replace_all(text, |caps: &regex::Captures| {
             if caps[0].starts_with("{{") && caps[0].ends_with("}}") {
                 // Escaped {} string, just return the inner string
                 caps[0][1..caps[0].len() - 1].to_owned()
             } else {
                 // Normal replacement of a variable
-                match self.get_property(&caps[1], cached_properties) {
-                    Some(value) => value.clone(),
+                match self.lookup(&caps[1], cached_properties) {
+                    Some(value) => property_text(&value),
                     None => caps[0].to_owned(), // Just return the matched string instead
                 }
             }
@@ -100,23 +354,90 @@ impl Request {
         .into_owned()
     }
 
+    /*
+        In JSON a quoted `"{name}"` becomes the property's JSON, so arrays,
+        objects and numbers go in as themselves rather than as strings.
+    */
+    fn replace_json(&self, text: &str, cached_properties: &PropertyMap) -> String {
+        JSON_RE
+            .replace_all(text, |caps: &regex::Captures| {
+                match self.lookup(&caps[1], cached_properties) {
+                    Some(value) => value.to_string(),
+                    None => caps[0].to_owned(),
+                }
+            })
+            .into_owned()
+    }
+
     // TODO: Replace these with in pre-prepared versions?
-    pub fn replaced_uri(&self, cached_properties: &StringMap) -> String {
+    pub fn replaced_uri(&self, cached_properties: &PropertyMap) -> String {
         self.replace_text(&self.uri, cached_properties)
     }
 
-    pub fn replaced_headers(&self, cached_properties: &StringMap) -> StringMap {
+    pub fn replaced_headers(&self, cached_properties: &PropertyMap) -> StringMap {
         let mut map = StringMap::new();
         self.headers.iter().for_each(|(key, value)| {
-            map.insert(key.clone(), self.replace_text(&value, cached_properties));
+            map.insert(key.clone(), self.replace_text(value, cached_properties));
         });
         map
     }
 
-    pub fn replaced_body(&self, cached_properties: &StringMap) -> Vec<u8> {
+    pub fn replaced_auth(&self, cached_properties: &PropertyMap) -> Option<Auth> {
+        self.auth
+            .as_ref()
+            .map(|auth| auth.map_text(|text| self.replace_text(text, cached_properties)))
+    }
+
+    pub fn replaced_signing(&self, cached_properties: &PropertyMap) -> Option<Signing> {
+        self.sign
+            .as_ref()
+            .map(|sign| sign.map_text(|text| self.replace_text(text, cached_properties)))
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    /*
+        Condition that has to hold to run, e.g. `{status_of.login} == 200`, over the
+        properties the request would be sent with, including its own and any `foreach` item.
+    */
+    pub fn when(&self) -> Option<&str> {
+        self.when.as_deref()
+    }
+    pub fn on_failure(&self) -> OnFailure {
+        self.on_failure.unwrap_or_default()
+    }
+    // The named request to run after this one, instead of the one that follows it
+    pub fn next(&self) -> Option<&str> {
+        self.next.as_deref()
+    }
+    // An array property to send the request once for each item of
+    pub fn foreach(&self) -> Option<&str> {
+        self.foreach.as_deref()
+    }
+    pub fn snapshot(&self) -> Option<&Snapshot> {
+        self.snapshot.as_ref()
+    }
+    // The compiled schema, once `load_schema` has run
+    pub fn schema(&self) -> Option<&Validator> {
+        self.validator.as_ref()
+    }
+
+    pub fn replaced_assertions(&self, cached_properties: &PropertyMap) -> Vec<Assertion> {
+        self.assert
+            .iter()
+            .map(|assertion| assertion.map_text(|text| self.replace_text(text, cached_properties)))
+            .collect()
+    }
+
+    pub fn replaced_body(&self, cached_properties: &PropertyMap) -> Vec<u8> {
         match self.content_type {
             ContentType::String => {
-                let replaced = self.replace_text(&self.body, cached_properties);
+                let body = match self.body.trim_start().starts_with(['{', '[']) {
+                    true => self.replace_json(&self.body, cached_properties),
+                    false => self.body.clone(),
+                };
+                let replaced = self.replace_text(&body, cached_properties);
                 replaced.into_bytes()
             }
             ContentType::Binary => self.body.clone().into_bytes(),
@@ -124,7 +445,7 @@ impl Request {
     }
 
     // I don't like this but I'm not sure there's much other way
-    pub fn update_body(&mut self, working_directory: &PathBuf) -> Result<()> {
+    pub fn update_body(&mut self, working_directory: &Path) -> Result<()> {
         if self.body.starts_with("file:") {
             // Load the file that we need and replace the body with it
             let file_path = self.body.strip_prefix("file:").unwrap();
@@ -136,34 +457,44 @@ impl Request {
             self.body = data;
         }
 
+        for assertion in self.assert.iter_mut() {
+            assertion.load_files(working_directory)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn load_schema(&mut self, working_directory: &Path) -> Result<()> {
+        if let Some(schema) = &self.schema {
+            self.validator = Some(schema::load(schema, working_directory)?);
+        }
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::{fs, path::PathBuf};
-
-    use super::{ContentType, Request, StringMap, Verb};
+    use super::{Auth, ContentType, ExtractMap, OnFailure, PropertyMap, Request, StringMap, Verb};
 
     struct RequestBuilder {
         uri: String,
-        properties: StringMap,
+        properties: PropertyMap,
         headers: StringMap,
         body: String,
         verb: Verb,
-        extract: StringMap,
+        extract: ExtractMap,
     }
 
+    #[allow(dead_code)]
     impl RequestBuilder {
         pub fn new() -> Self {
             RequestBuilder {
                 uri: String::new(),
-                properties: StringMap::new(),
+                properties: PropertyMap::new(),
                 headers: StringMap::new(),
                 body: String::new(),
                 verb: Verb::GET,
-                extract: StringMap::new(),
+                extract: ExtractMap::new(),
             }
         }
 
@@ -172,7 +503,7 @@ mod test {
             self
         }
 
-        pub fn properties(mut self, test: StringMap) -> RequestBuilder {
+        pub fn properties(mut self, test: PropertyMap) -> RequestBuilder {
             self.properties = test;
             self
         }
@@ -197,7 +528,7 @@ mod test {
             self
         }
 
-        pub fn extract_map(mut self, extract: StringMap) -> RequestBuilder {
+        pub fn extract_map(mut self, extract: ExtractMap) -> RequestBuilder {
             self.extract = extract;
             self
         }
@@ -205,20 +536,35 @@ mod test {
         pub fn build(self) -> Request {
             Request {
                 uri: self.uri,
+                name: None,
+                when: None,
+                on_failure: None,
+                next: None,
+                foreach: None,
                 properties: self.properties,
                 headers: self.headers,
                 body: self.body,
                 verb: self.verb,
                 extract: self.extract,
-                content_type: ContentType::String
+                assert: Vec::new(),
+                schema: None,
+                validator: None,
+                snapshot: None,
+                content_type: ContentType::String,
+                compress: None,
+                poll: None,
+                redirects: Default::default(),
+                proxy: None,
+                auth: None,
+                sign: None,
             }
         }
     }
 
     #[test]
     fn uri_replaced() {
-        let mut props = StringMap::new();
-        props.insert("some_key".to_owned(), "some_value".to_owned());
+        let mut props = PropertyMap::new();
+        props.insert("some_key".to_owned(), "some_value".into());
 
         let request = RequestBuilder::new()
             .uri("URI/{some_key}/URI".to_owned())
@@ -226,7 +572,7 @@ mod test {
             .build();
 
         assert_eq!(
-            request.replaced_uri(&StringMap::new()),
+            request.replaced_uri(&PropertyMap::new()),
             "URI/some_value/URI"
         );
     }
@@ -234,8 +580,8 @@ mod test {
     #[test]
     fn uri_replaced_escaped_braces() {
         // It should escape the key even if it's available in the property maps
-        let mut props = StringMap::new();
-        props.insert("some_key".to_owned(), "some_value".to_owned());
+        let mut props = PropertyMap::new();
+        props.insert("some_key".to_owned(), "some_value".into());
 
         let request = RequestBuilder::new()
             .uri("URI/{{some_key}}/URI".to_owned())
@@ -243,7 +589,7 @@ mod test {
             .build();
 
         assert_eq!(
-            request.replaced_uri(&StringMap::new()),
+            request.replaced_uri(&PropertyMap::new()),
             "URI/{some_key}/URI"
         );
     }
@@ -252,7 +598,7 @@ mod test {
     fn uri_replaced_empty_escaped_braces() {
         let request = RequestBuilder::new().uri("URI/{{}}/URI".to_owned()).build();
 
-        assert_eq!(request.replaced_uri(&StringMap::new()), "URI/{}/URI");
+        assert_eq!(request.replaced_uri(&PropertyMap::new()), "URI/{}/URI");
     }
 
     #[test]
@@ -262,22 +608,33 @@ mod test {
             .build();
 
         assert_eq!(
-            request.replaced_uri(&StringMap::new()),
+            request.replaced_uri(&PropertyMap::new()),
             "URI/{some_key}/URI"
         );
     }
 
+    #[test]
+    fn uri_replaced_dotted_key() {
+        let mut props = PropertyMap::new();
+        props.insert("status_of.login".to_owned(), 200.into());
+        let request = RequestBuilder::new()
+            .uri("URI/{status_of.login}/URI".to_owned())
+            .build();
+
+        assert_eq!(request.replaced_uri(&props), "URI/200/URI");
+    }
+
     #[test]
     fn uri_replaced_empty_key() {
         let request = RequestBuilder::new().uri("URI/{}/URI".to_owned()).build();
 
-        assert_eq!(request.replaced_uri(&StringMap::new()), "URI/{}/URI");
+        assert_eq!(request.replaced_uri(&PropertyMap::new()), "URI/{}/URI");
     }
 
     #[test]
     fn headers_replaced() {
-        let mut props = StringMap::new();
-        props.insert("some_key".to_owned(), "some_value".to_owned());
+        let mut props = PropertyMap::new();
+        props.insert("some_key".to_owned(), "some_value".into());
 
         let request = RequestBuilder::new()
             .header("A_HEADER".to_owned(), "{some_key}".to_owned())
@@ -285,7 +642,9 @@ mod test {
             .build();
 
         assert_eq!(
-            request.replaced_headers(&StringMap::new()).get("A_HEADER"),
+            request
+                .replaced_headers(&PropertyMap::new())
+                .get("A_HEADER"),
             Some(&"some_value".to_owned())
         );
     }
@@ -297,7 +656,9 @@ mod test {
             .build();
 
         assert_eq!(
-            request.replaced_headers(&StringMap::new()).get("A_HEADER"),
+            request
+                .replaced_headers(&PropertyMap::new())
+                .get("A_HEADER"),
             Some(&"{some_key}".to_owned())
         );
     }
@@ -309,7 +670,9 @@ mod test {
             .build();
 
         assert_eq!(
-            request.replaced_headers(&StringMap::new()).get("A_HEADER"),
+            request
+                .replaced_headers(&PropertyMap::new())
+                .get("A_HEADER"),
             Some(&"{}".to_owned())
         );
     }
@@ -321,7 +684,9 @@ mod test {
             .build();
 
         assert_eq!(
-            request.replaced_headers(&StringMap::new()).get("A_HEADER"),
+            request
+                .replaced_headers(&PropertyMap::new())
+                .get("A_HEADER"),
             Some(&"{some_key}".to_owned())
         );
     }
@@ -333,7 +698,7 @@ mod test {
             "verb": "GET"
         }"#;
 
-        let value = serde_json::from_str::<Request>(&data);
+        let value = serde_json::from_str::<Request>(data);
         assert!(
             value.is_ok(),
             "Failed to parse basic string: {}",
@@ -349,7 +714,7 @@ mod test {
             "body": "hello"
         }"#;
 
-        let value = serde_json::from_str::<Request>(&data);
+        let value = serde_json::from_str::<Request>(data);
         assert!(
             value.is_ok(),
             "Failed to parse body string: {}",
@@ -358,6 +723,171 @@ mod test {
         assert_eq!(value.unwrap().body(), "hello".as_bytes().to_vec());
     }
 
+    #[test]
+    fn poll_serialisation() {
+        let data = r#"{
+            "uri": "http://some.website.com/jobs/1",
+            "verb": "GET",
+            "poll": {
+                "until": "json:status == \"done\"",
+                "interval_ms": 250
+            }
+        }"#;
+
+        let value = serde_json::from_str::<Request>(data);
+        assert!(
+            value.is_ok(),
+            "Failed to parse poll string: {}",
+            value.unwrap_err()
+        );
+        let value = value.unwrap();
+        let poll = value.poll().expect("Poll should be set");
+        assert_eq!(poll.until(), "json:status == \"done\"");
+        assert_eq!(poll.interval().as_millis(), 250);
+        assert_eq!(poll.timeout().as_millis(), 30000);
+    }
+
+    #[test]
+    fn auth_replaced() {
+        let mut props = PropertyMap::new();
+        props.insert("token".to_owned(), "abc".into());
+
+        let mut request = RequestBuilder::new().properties(props).build();
+        request.inherit_auth(Some(&Auth::Bearer {
+            token: "{token}".to_owned(),
+        }));
+
+        assert_eq!(
+            request.replaced_auth(&PropertyMap::new()),
+            Some(Auth::Bearer {
+                token: "abc".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn auth_not_inherited_when_set() {
+        let data = r#"{
+            "uri": "http://some.website.com",
+            "verb": "GET",
+            "auth": { "type": "none" }
+        }"#;
+
+        let mut request = serde_json::from_str::<Request>(data).unwrap();
+        request.inherit_auth(Some(&Auth::Bearer {
+            token: "abc".to_owned(),
+        }));
+        assert_eq!(request.replaced_auth(&PropertyMap::new()), Some(Auth::None));
+    }
+
+    #[test]
+    fn typed_values_in_json_body() {
+        let data = r#"{
+            "uri": "http://some.website.com/{id}",
+            "verb": "POST",
+            "body": "{ \"items\": \"{items}\", \"id\": {id}, \"label\": \"item {id}\", \"name\": \"{name}\" }"
+        }"#;
+        let request = serde_json::from_str::<Request>(data).unwrap();
+
+        let mut props = PropertyMap::new();
+        props.insert("items".to_owned(), serde_json::json!([1, { "a": null }]));
+        props.insert("id".to_owned(), serde_json::json!(7));
+        props.insert("name".to_owned(), serde_json::json!("say \"hi\""));
+
+        assert_eq!(request.replaced_uri(&props), "http://some.website.com/7");
+        let body = String::from_utf8(request.replaced_body(&props)).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({
+                "items": [1, { "a": null }],
+                "id": 7,
+                "label": "item 7",
+                "name": "say \"hi\""
+            })
+        );
+    }
+
+    #[test]
+    fn generated_values() {
+        let data = r#"{
+            "uri": "http://some.website.com/{$uuid}?at={$timestamp}&keep={{$uuid}}",
+            "verb": "POST",
+            "body": "{ \"id\": \"{$uuid}\", \"n\": \"{$random_int}\", \"other\": \"{$unknown}\" }"
+        }"#;
+        let request = serde_json::from_str::<Request>(data).unwrap();
+        let props = PropertyMap::new();
+
+        let uri = request.replaced_uri(&props);
+        let (uuid, rest) = uri
+            .strip_prefix("http://some.website.com/")
+            .unwrap()
+            .split_once('?')
+            .unwrap();
+        assert_eq!(uuid.len(), 36);
+        assert_eq!(&uuid[14..15], "4");
+        assert!(rest.ends_with("&keep={$uuid}"));
+        assert_ne!(request.replaced_uri(&props), uri);
+
+        let body = String::from_utf8(request.replaced_body(&props)).unwrap();
+        let body = serde_json::from_str::<serde_json::Value>(&body).unwrap();
+        assert_eq!(body["id"].as_str().unwrap().len(), 36);
+        assert!(body["n"].as_u64().unwrap() < 1000);
+        assert_eq!(body["other"], "{$unknown}");
+    }
+
+    #[test]
+    fn random_int_range() {
+        let values: Vec<u64> = (0..2000)
+            .map(|_| {
+                super::generated_value("$random_int")
+                    .unwrap()
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect();
+        assert!(values.iter().all(|value| *value < 1000));
+        assert!(values.iter().any(|value| *value >= 900));
+    }
+
+    #[test]
+    fn control_flow_serialisation() {
+        let data = r#"{
+            "uri": "http://some.website.com",
+            "verb": "GET",
+            "on_failure": "stop_collection",
+            "next": "login"
+        }"#;
+        let mut value = serde_json::from_str::<Request>(data).unwrap();
+        value.inherit_on_failure(Some(OnFailure::StopRun));
+        assert_eq!(value.on_failure(), OnFailure::StopCollection);
+        assert_eq!(value.next(), Some("login"));
+
+        let data = r#"{ "uri": "http://some.website.com", "verb": "GET" }"#;
+        let mut value = serde_json::from_str::<Request>(data).unwrap();
+        assert_eq!(value.on_failure(), OnFailure::Continue);
+        value.inherit_on_failure(Some(OnFailure::StopRun));
+        assert_eq!(value.on_failure(), OnFailure::StopRun);
+    }
+
+    #[test]
+    fn redirect_serialisation() {
+        let data = r#"{
+            "uri": "http://some.website.com",
+            "verb": "GET",
+            "max_redirects": 3
+        }"#;
+
+        let mut value = serde_json::from_str::<Request>(data).unwrap();
+        assert!(value.redirects().follow());
+        assert_eq!(value.redirects().max(), 3);
+
+        let defaults: super::RedirectOptions =
+            serde_json::from_str(r#"{ "follow_redirects": false, "max_redirects": 5 }"#).unwrap();
+        value.redirects_mut().inherit(&defaults);
+        assert!(!value.redirects().follow());
+        assert_eq!(value.redirects().max(), 3);
+    }
+
     /*
 
     Disabled for now as github actions seem to not like writing files out?
@@ -372,7 +902,7 @@ mod test {
             "body": "file:hello.txt"
         }"#;
 
-        let mut value = serde_json::from_str::<Request>(&data).unwrap();
+        let mut value = serde_json::from_str::<Request>(data).unwrap();
         assert!(value.update_body(&tmp_dir).is_ok());
         assert_eq!(value.body(), "hello".as_bytes().to_vec());
     }
@@ -388,7 +918,7 @@ mod test {
             "body": "file:hello.bin"
         }"#;
 
-        let mut value = serde_json::from_str::<Request>(&data).unwrap();
+        let mut value = serde_json::from_str::<Request>(data).unwrap();
         assert!(value.update_body(&tmp_dir).is_ok());
         assert_eq!(value.body(), "hello".as_bytes().to_vec());
     }
@@ -1,30 +1,128 @@
+use crate::charset;
 use crate::common::StringMap;
+use std::fmt;
+use std::time::Duration;
+
+// A single hop in a redirect chain
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    pub status: u16,
+    pub from: String,
+    pub to: String,
+}
+
+/*
+    Where the time went while making a request. Connection phases are zero when a
+    pooled connection was reused, and every field is summed over redirect hops.
+    `ttfb` is the wait between the connection being ready and the response headers.
+*/
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timings {
+    pub dns: Duration,
+    pub connect: Duration,
+    pub tls: Duration,
+    pub ttfb: Duration,
+    pub transfer: Duration,
+    pub decompress: Duration,
+    pub total: Duration,
+    pub new_connections: usize,
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DNS {}ms, connect {}ms, TLS {}ms, TTFB {}ms, transfer {}ms, decompress {}ms (total {}ms, {} new connection(s))",
+            self.dns.as_millis(),
+            self.connect.as_millis(),
+            self.tls.as_millis(),
+            self.ttfb.as_millis(),
+            self.transfer.as_millis(),
+            self.decompress.as_millis(),
+            self.total.as_millis(),
+            self.new_connections
+        )
+    }
+}
+
+/*
+    The first hop as it went out, jar cookies, signatures and digest answers
+    included, for `request.` extractors. A compressed body is kept as it was
+    before compression.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct SentRequest {
+    pub method: String,
+    pub uri: String,
+    pub headers: StringMap,
+    pub body: Vec<u8>,
+}
 
 #[derive(Debug)]
 pub struct Response {
     status: u16,
     body: Vec<u8>,
-    headers: StringMap
+    headers: StringMap,
+    url: String,
+    redirects: Vec<Redirect>,
+    cookies: StringMap,
+    timings: Timings,
+    request: SentRequest,
 }
 
 impl Response {
-  pub fn body(&self) -> &Vec<u8> {
-    &self.body
-  }
+    pub fn body(&self) -> &Vec<u8> {
+        &self.body
+    }
 
-  pub fn status(&self) -> u16 {
-    self.status
-  }
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn headers(&self) -> &StringMap {
+        &self.headers
+    }
+
+    // The final URL, after any redirects were followed
+    pub fn url(&self) -> &String {
+        &self.url
+    }
 
-  pub fn headers(&self) -> &StringMap {
-    &self.headers
-  }
+    pub fn redirects(&self) -> &Vec<Redirect> {
+        &self.redirects
+    }
+
+    // Cookies set by the response, along with any from the jar for its URL
+    pub fn cookies(&self) -> &StringMap {
+        &self.cookies
+    }
+
+    pub fn timings(&self) -> &Timings {
+        &self.timings
+    }
+
+    pub fn request(&self) -> &SentRequest {
+        &self.request
+    }
+
+    // The body decoded as text using its declared charset, or None if it's binary
+    pub fn text(&self) -> Option<String> {
+        charset::decode(
+            &self.body,
+            self.headers.get("content-type").map(|value| value.as_str()),
+        )
+    }
 }
 
 pub struct ResponseBuilder {
     status: u16,
     body: Vec<u8>,
-    headers: StringMap
+    headers: StringMap,
+    url: String,
+    redirects: Vec<Redirect>,
+    cookies: StringMap,
+    timings: Timings,
+    request: SentRequest,
 }
 
 impl ResponseBuilder {
@@ -32,7 +130,12 @@ impl ResponseBuilder {
         ResponseBuilder {
             status: 0,
             body: Vec::new(),
-            headers: StringMap::new()
+            headers: StringMap::new(),
+            url: String::new(),
+            redirects: Vec::new(),
+            cookies: StringMap::new(),
+            timings: Timings::default(),
+            request: SentRequest::default(),
         }
     }
 
@@ -51,11 +154,41 @@ impl ResponseBuilder {
         self
     }
 
+    pub fn url(mut self, url: String) -> ResponseBuilder {
+        self.url = url;
+        self
+    }
+
+    pub fn redirects(mut self, redirects: Vec<Redirect>) -> ResponseBuilder {
+        self.redirects = redirects;
+        self
+    }
+
+    pub fn cookies(mut self, cookies: StringMap) -> ResponseBuilder {
+        self.cookies = cookies;
+        self
+    }
+
+    pub fn timings(mut self, timings: Timings) -> ResponseBuilder {
+        self.timings = timings;
+        self
+    }
+
+    pub fn request(mut self, request: SentRequest) -> ResponseBuilder {
+        self.request = request;
+        self
+    }
+
     pub fn build(self) -> Response {
         Response {
             status: self.status,
             body: self.body,
-            headers: self.headers
+            headers: self.headers,
+            url: self.url,
+            redirects: self.redirects,
+            cookies: self.cookies,
+            timings: self.timings,
+            request: self.request,
         }
     }
 }
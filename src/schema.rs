@@ -0,0 +1,126 @@
+use crate::response::Response;
+use anyhow::{anyhow, Context, Result};
+use jsonschema::Validator;
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+/*
+  JSON Schema validation of response bodies. The draft comes from the schema's
+  `$schema`, and is 2020-12 when it doesn't say. Schemas are given inline on the
+  request, or as `file:<path>` relative to the collection, like bodies.
+  They're compiled when the collection loads, so a broken one stops it loading.
+*/
+
+// Reads a `file:` schema, then compiles it
+pub fn load(schema: &Value, working_directory: &Path) -> Result<Validator> {
+    let loaded;
+    let schema = match schema {
+        Value::String(text) => {
+            let file_path = text
+                .strip_prefix("file:")
+                .ok_or_else(|| anyhow!("A schema is either inline JSON or a file: path"))?;
+            let file_path = working_directory.join(file_path);
+            let data = fs::read_to_string(&file_path)
+                .with_context(|| format!("Failed to load {}", file_path.display()))?;
+            loaded = serde_json::from_str(&data)
+                .with_context(|| format!("Schema {} isn't valid JSON", file_path.display()))?;
+            &loaded
+        }
+        schema => schema,
+    };
+    jsonschema::validator_for(schema).map_err(|e| anyhow!("Invalid schema: {}", e))
+}
+
+// Every way the body breaks the schema, each as `<JSON pointer>: <problem>`
+pub fn violations(validator: &Validator, response: &Response) -> Result<Vec<String>> {
+    let body = response
+        .text()
+        .ok_or_else(|| anyhow!("Body is binary, not JSON"))?;
+    let body: Value = serde_json::from_str(&body).with_context(|| "Body isn't valid JSON")?;
+
+    Ok(validator
+        .iter_errors(&body)
+        .map(|error| {
+            let pointer = error.instance_path.to_string();
+            match pointer.is_empty() {
+                true => format!("/: {}", error),
+                false => format!("{}: {}", pointer, error),
+            }
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::response::ResponseBuilder;
+    use serde_json::json;
+    use std::fs;
+    use std::path::Path;
+
+    fn validator() -> jsonschema::Validator {
+        super::load(&schema(), Path::new(".")).unwrap()
+    }
+
+    fn schema() -> serde_json::Value {
+        json!({
+            "type": "object",
+            "required": ["id", "items"],
+            "properties": {
+                "id": { "type": "integer" },
+                "items": {
+                    "type": "array",
+                    "prefixItems": [{ "type": "string" }],
+                    "items": { "type": "number" }
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn violations() {
+        let body = json!({ "id": 1, "items": ["first", 2, 3.5] }).to_string();
+        let response = ResponseBuilder::new().body(body.into_bytes()).build();
+        assert!(super::violations(&validator(), &response)
+            .unwrap()
+            .is_empty());
+
+        // `prefixItems` is new in 2020-12, so this also checks that's the default draft
+        let body = json!({ "id": "one", "items": [1, "two"] }).to_string();
+        let response = ResponseBuilder::new().body(body.into_bytes()).build();
+        let mut violations = super::violations(&validator(), &response).unwrap();
+        violations.sort();
+        assert_eq!(violations.len(), 3, "{:?}", violations);
+        assert!(violations[0].starts_with("/id: "));
+        assert!(violations[1].starts_with("/items/0: "));
+        assert!(violations[2].starts_with("/items/1: "));
+
+        let response = ResponseBuilder::new().body(b"[]".to_vec()).build();
+        let violations = super::violations(&validator(), &response).unwrap();
+        assert!(violations[0].starts_with("/: "));
+
+        let response = ResponseBuilder::new().body(b"{ nope".to_vec()).build();
+        assert!(super::violations(&validator(), &response).is_err());
+    }
+
+    #[test]
+    fn load_file() {
+        let tmp_dir = crate::common::scratch_dir("load_file");
+        fs::write(tmp_dir.join("schema.json"), schema().to_string()).unwrap();
+
+        let validator = super::load(&json!("file:schema.json"), &tmp_dir).unwrap();
+        assert!(validator.is_valid(&json!({ "id": 1, "items": [] })));
+        assert!(!validator.is_valid(&json!({ "id": "one", "items": [] })));
+        assert!(super::load(&json!("schema.json"), &tmp_dir).is_err());
+        assert!(super::load(&json!("file:missing.json"), &tmp_dir).is_err());
+
+        // Schemas that can't compile are caught as they load, not once a response arrives
+        assert!(super::load(&json!({ "type": 5 }), &tmp_dir).is_err());
+        fs::write(
+            tmp_dir.join("broken_schema.json"),
+            r#"{ "minimum": "five" }"#,
+        )
+        .unwrap();
+        assert!(super::load(&json!("file:broken_schema.json"), &tmp_dir).is_err());
+    }
+}
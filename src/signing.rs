@@ -0,0 +1,536 @@
+use anyhow::{anyhow, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use hyper::header::{self, HeaderName, HeaderValue};
+use hyper::{HeaderMap, Method, Uri};
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha512};
+use std::env;
+
+/*
+  Signs the final request, after the URI, headers and body have had their
+  properties replaced and any auth has been added. Set with a `sign` block such
+  as `{ "type": "aws_sigv4", "service": "execute-api" }`, on a request or as a
+  collection default; `{ "type": "none" }` opts out. Requests are signed again
+  for every redirect hop, as the signature covers the URI.
+*/
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HmacAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Encoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+fn default_signature_header() -> String {
+    "X-Signature".to_owned()
+}
+
+fn default_timestamp_header() -> String {
+    "X-Timestamp".to_owned()
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Signing {
+    None,
+    // Anything left out is read from the usual AWS_* environment variables
+    #[serde(rename = "aws_sigv4")]
+    AwsSigV4 {
+        service: String,
+        #[serde(default)]
+        region: Option<String>,
+        #[serde(default)]
+        access_key: Option<String>,
+        #[serde(default)]
+        secret_key: Option<String>,
+        #[serde(default)]
+        session_token: Option<String>,
+    },
+    /*
+        Signs `METHOD\npath?query\ntimestamp\nbody` with the key, sending the
+        unix timestamp and the signature in their own headers.
+    */
+    Hmac {
+        key: String,
+        #[serde(default)]
+        algorithm: HmacAlgorithm,
+        #[serde(default)]
+        encoding: Encoding,
+        #[serde(default = "default_signature_header")]
+        header: String,
+        #[serde(default = "default_timestamp_header")]
+        timestamp_header: String,
+    },
+}
+
+impl Signing {
+    // Run every user supplied string through `f`, used for property replacement
+    pub fn map_text(&self, f: impl Fn(&str) -> String) -> Signing {
+        match self {
+            Signing::None => Signing::None,
+            Signing::AwsSigV4 {
+                service,
+                region,
+                access_key,
+                secret_key,
+                session_token,
+            } => Signing::AwsSigV4 {
+                service: f(service),
+                region: region.as_deref().map(&f),
+                access_key: access_key.as_deref().map(&f),
+                secret_key: secret_key.as_deref().map(&f),
+                session_token: session_token.as_deref().map(&f),
+            },
+            Signing::Hmac {
+                key,
+                algorithm,
+                encoding,
+                header,
+                timestamp_header,
+            } => Signing::Hmac {
+                key: f(key),
+                algorithm: *algorithm,
+                encoding: *encoding,
+                header: header.clone(),
+                timestamp_header: timestamp_header.clone(),
+            },
+        }
+    }
+
+    // Whether signing writes the Authorization header, so can't be used with auth that does too
+    pub fn sets_authorization(&self) -> bool {
+        match self {
+            Signing::None => false,
+            Signing::AwsSigV4 { .. } => true,
+            Signing::Hmac { header, .. } => header.eq_ignore_ascii_case("authorization"),
+        }
+    }
+}
+
+fn mac(algorithm: HmacAlgorithm, key: &[u8], data: &[u8]) -> Vec<u8> {
+    match algorithm {
+        HmacAlgorithm::Sha256 => {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+        HmacAlgorithm::Sha512 => {
+            let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC takes keys of any size");
+            mac.update(data);
+            mac.finalize().into_bytes().to_vec()
+        }
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value).with_context(|| "Invalid characters in signature value")
+}
+
+fn header_name(name: &str) -> Result<HeaderName> {
+    HeaderName::from_bytes(name.as_bytes())
+        .with_context(|| format!("Invalid signature header name [{}]", name))
+}
+
+fn from_env(value: &Option<String>, names: &[&str]) -> Option<String> {
+    value
+        .clone()
+        .or_else(|| names.iter().find_map(|name| env::var(name).ok()))
+}
+
+mod sigv4 {
+    use super::{mac, HmacAlgorithm};
+    use hyper::{HeaderMap, Method, Uri};
+    use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+    use sha2::{Digest, Sha256};
+
+    // Everything but the unreserved characters gets encoded
+    const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+        .remove(b'-')
+        .remove(b'_')
+        .remove(b'.')
+        .remove(b'~');
+
+    pub struct Credentials<'a> {
+        pub access_key: &'a str,
+        pub secret_key: &'a str,
+        pub region: &'a str,
+        pub service: &'a str,
+    }
+
+    fn encode(text: &str) -> String {
+        utf8_percent_encode(text, UNRESERVED).to_string()
+    }
+
+    fn canonical_path(uri: &Uri, service: &str) -> String {
+        let path = match uri.path() {
+            "" => "/",
+            path => path,
+        };
+        // S3 wants the path as it is, everything else has each segment encoded again
+        if service == "s3" {
+            return path.to_owned();
+        }
+        path.split('/').map(encode).collect::<Vec<_>>().join("/")
+    }
+
+    pub fn canonical_query(uri: &Uri) -> String {
+        let decode = |s: &str| {
+            percent_decode_str(&s.replace('+', " "))
+                .decode_utf8_lossy()
+                .into_owned()
+        };
+        let mut pairs: Vec<(String, String)> = uri
+            .query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((key, value)) => (encode(&decode(key)), encode(&decode(value))),
+                None => (encode(&decode(pair)), String::new()),
+            })
+            .collect();
+        pairs.sort();
+        pairs
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    // The canonical header block, and the `;` separated list of the names signed
+    fn canonical_headers(headers: &HeaderMap) -> (String, String) {
+        let mut names: Vec<&str> = headers.keys().map(|name| name.as_str()).collect();
+        names.sort();
+        names.dedup();
+
+        let block = names
+            .iter()
+            .map(|name| {
+                let values: Vec<String> = headers
+                    .get_all(*name)
+                    .iter()
+                    .map(|value| {
+                        String::from_utf8_lossy(value.as_bytes())
+                            .split_whitespace()
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    })
+                    .collect();
+                format!("{}:{}\n", name, values.join(","))
+            })
+            .collect::<String>();
+        (block, names.join(";"))
+    }
+
+    /*
+        The Authorization header for a request already carrying its Host and
+        X-Amz-Date headers, as every header present gets signed.
+    */
+    pub fn authorization(
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+        body: &[u8],
+        amz_date: &str,
+        credentials: &Credentials,
+    ) -> String {
+        let (header_block, signed_headers) = canonical_headers(headers);
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            canonical_path(uri, credentials.service),
+            canonical_query(uri),
+            header_block,
+            signed_headers,
+            hex::encode(Sha256::digest(body))
+        );
+
+        let date = &amz_date[..8];
+        let scope = format!(
+            "{}/{}/{}/aws4_request",
+            date, credentials.region, credentials.service
+        );
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let sign = |key: &[u8], data: &str| mac(HmacAlgorithm::Sha256, key, data.as_bytes());
+        let key = sign(format!("AWS4{}", credentials.secret_key).as_bytes(), date);
+        let key = sign(&key, credentials.region);
+        let key = sign(&key, credentials.service);
+        let key = sign(&key, "aws4_request");
+        let signature = hex::encode(sign(&key, &string_to_sign));
+
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            credentials.access_key, scope, signed_headers, signature
+        )
+    }
+}
+
+// The message signed by `hmac` signing
+fn hmac_message(method: &Method, uri: &Uri, timestamp: &str, body: &[u8]) -> Vec<u8> {
+    let path = uri
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    let mut message = format!("{}\n{}\n{}\n", method, path, timestamp).into_bytes();
+    message.extend_from_slice(body);
+    message
+}
+
+pub fn sign(
+    signing: &Signing,
+    method: &Method,
+    uri: &Uri,
+    headers: &mut HeaderMap,
+    body: &[u8],
+    now: DateTime<Utc>,
+) -> Result<()> {
+    match signing {
+        Signing::None => {}
+        Signing::AwsSigV4 {
+            service,
+            region,
+            access_key,
+            secret_key,
+            session_token,
+        } => {
+            let region = from_env(region, &["AWS_REGION", "AWS_DEFAULT_REGION"])
+                .ok_or_else(|| anyhow!("No region to sign with, set `region` or AWS_REGION"))?;
+            let access_key = from_env(access_key, &["AWS_ACCESS_KEY_ID"]).ok_or_else(|| {
+                anyhow!("No access key to sign with, set `access_key` or AWS_ACCESS_KEY_ID")
+            })?;
+            let secret_key = from_env(secret_key, &["AWS_SECRET_ACCESS_KEY"]).ok_or_else(|| {
+                anyhow!("No secret key to sign with, set `secret_key` or AWS_SECRET_ACCESS_KEY")
+            })?;
+            let session_token = from_env(session_token, &["AWS_SESSION_TOKEN"]);
+
+            /*
+                The signature replaces any other Authorization, so mustn't cover it.
+                Collections with auth that sets one as well don't load.
+            */
+            headers.remove(header::AUTHORIZATION);
+            // Host is signed, so set it ourselves rather than leaving it to hyper
+            let host = uri
+                .authority()
+                .ok_or_else(|| anyhow!("Can't sign [{}] as it has no host", uri))?;
+            headers.insert(header::HOST, header_value(host.as_str())?);
+            let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+            headers.insert("x-amz-date", header_value(&amz_date)?);
+            if let Some(token) = session_token {
+                headers.insert("x-amz-security-token", header_value(&token)?);
+            }
+            if service == "s3" {
+                headers.insert(
+                    "x-amz-content-sha256",
+                    header_value(&hex::encode(Sha256::digest(body)))?,
+                );
+            }
+
+            let authorization = sigv4::authorization(
+                method,
+                uri,
+                headers,
+                body,
+                &amz_date,
+                &sigv4::Credentials {
+                    access_key: &access_key,
+                    secret_key: &secret_key,
+                    region: &region,
+                    service,
+                },
+            );
+            headers.insert(header::AUTHORIZATION, header_value(&authorization)?);
+        }
+        Signing::Hmac {
+            key,
+            algorithm,
+            encoding,
+            header,
+            timestamp_header,
+        } => {
+            let timestamp = now.timestamp().to_string();
+            let signature = mac(
+                *algorithm,
+                key.as_bytes(),
+                &hmac_message(method, uri, &timestamp, body),
+            );
+            let signature = match encoding {
+                Encoding::Hex => hex::encode(signature),
+                Encoding::Base64 => STANDARD.encode(signature),
+            };
+            headers.insert(header_name(timestamp_header)?, header_value(&timestamp)?);
+            headers.insert(header_name(header)?, header_value(&signature)?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{mac, sign, sigv4, HmacAlgorithm, Signing};
+    use chrono::{TimeZone, Utc};
+    use hyper::header::{self, HeaderMap, HeaderValue};
+    use hyper::{Method, Uri};
+
+    #[test]
+    fn hmac_rfc4231() {
+        // Test case 2 from RFC 4231
+        let data = b"what do ya want for nothing?";
+        assert_eq!(
+            hex::encode(mac(HmacAlgorithm::Sha256, b"Jefe", data)),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex::encode(mac(HmacAlgorithm::Sha512, b"Jefe", data)),
+            "164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"
+        );
+    }
+
+    // Signs a request from the AWS SigV4 test suite, which all use the same date and credentials
+    fn sigv4_suite(
+        method: Method,
+        path: &str,
+        extra: &[(&'static str, &'static str)],
+        body: &[u8],
+    ) -> String {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::HOST,
+            HeaderValue::from_static("example.amazonaws.com"),
+        );
+        headers.insert("x-amz-date", HeaderValue::from_static("20150830T123600Z"));
+        for (name, value) in extra {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        sigv4::authorization(
+            &method,
+            &format!("https://example.amazonaws.com{}", path)
+                .parse::<Uri>()
+                .unwrap(),
+            &headers,
+            body,
+            "20150830T123600Z",
+            &sigv4::Credentials {
+                access_key: "AKIDEXAMPLE",
+                secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                region: "us-east-1",
+                service: "service",
+            },
+        )
+    }
+
+    #[test]
+    fn sigv4_get_vanilla() {
+        assert_eq!(
+            sigv4_suite(Method::GET, "/", &[], b""),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn sigv4_queries() {
+        // `get-vanilla-query-order-key-case`, where the parameters have to be sorted
+        assert!(
+            sigv4_suite(Method::GET, "/?Param2=value2&Param1=value1", &[], b"").ends_with(
+                "Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"
+            )
+        );
+
+        // `get-vanilla-query-unreserved`, where nothing should be encoded
+        let unreserved = "-._~0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+        let path = format!("/?{}={}", unreserved, unreserved);
+        assert!(sigv4_suite(Method::GET, &path, &[], b"").ends_with(
+            "Signature=9c3e54bfcdf0b19771a7f523ee5669cdf59bc7cc0884027167c21bb143a40197"
+        ));
+
+        // Reserved characters are encoded, and keys without values keep an empty one
+        let uri = "http://example.com/?b=&a=x+y%2F%3D&c"
+            .parse::<Uri>()
+            .unwrap();
+        assert_eq!(sigv4::canonical_query(&uri), "a=x%20y%2F%3D&b=&c=");
+    }
+
+    #[test]
+    fn sigv4_post_form() {
+        // `post-x-www-form-urlencoded`, where the body is hashed and Content-Type signed
+        assert_eq!(
+            sigv4_suite(
+                Method::POST,
+                "/",
+                &[("content-type", "application/x-www-form-urlencoded")],
+                b"Param1=value1"
+            ),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a"
+        );
+    }
+
+    #[test]
+    fn sigv4_sets_headers() {
+        let signing: Signing = serde_json::from_str(
+            r#"{ "type": "aws_sigv4", "service": "service", "region": "us-east-1",
+                 "access_key": "AKIDEXAMPLE", "secret_key": "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY" }"#,
+        )
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        sign(
+            &signing,
+            &Method::GET,
+            &"https://example.amazonaws.com/".parse::<Uri>().unwrap(),
+            &mut headers,
+            b"",
+            Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(headers.get(header::HOST).unwrap(), "example.amazonaws.com");
+        assert_eq!(headers.get("x-amz-date").unwrap(), "20150830T123600Z");
+        assert!(headers
+            .get(header::AUTHORIZATION)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .ends_with(
+                "Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+            ));
+    }
+
+    #[test]
+    fn hmac_signing() {
+        let signing: Signing =
+            serde_json::from_str(r#"{ "type": "hmac", "key": "Jefe", "encoding": "base64" }"#)
+                .unwrap();
+        let mut headers = HeaderMap::new();
+        let uri = "http://example.com/orders?page=2".parse::<Uri>().unwrap();
+        let now = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+        sign(&signing, &Method::POST, &uri, &mut headers, b"{}", now).unwrap();
+
+        assert_eq!(headers.get("X-Timestamp").unwrap(), "1704164645");
+        let message = b"POST\n/orders?page=2\n1704164645\n{}";
+        let expected = base64::Engine::encode(
+            &base64::engine::general_purpose::STANDARD,
+            mac(HmacAlgorithm::Sha256, b"Jefe", message),
+        );
+        assert_eq!(headers.get("X-Signature").unwrap(), expected.as_str());
+    }
+}
@@ -0,0 +1,385 @@
+use crate::extractor::json;
+use crate::response::Response;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/*
+  Golden file checks of response bodies. The first run stores the body in the
+  collection's `_snapshots` directory, pretty-printed if it's JSON, and later
+  runs compare against it. `ignore` takes JSONPaths for fields that change
+  every run, like timestamps and UUIDs. `--update-snapshots` rewrites them.
+*/
+
+// `"snapshot": true`, or `{ "name": "...", "ignore": ["$.created_at"] }`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Snapshot {
+    Enabled(bool),
+    Options {
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(default)]
+        ignore: Vec<String>,
+    },
+}
+
+impl Snapshot {
+    fn enabled(&self) -> bool {
+        !matches!(self, Snapshot::Enabled(false))
+    }
+
+    fn name(&self) -> Option<&str> {
+        match self {
+            Snapshot::Options { name, .. } => name.as_deref(),
+            _ => None,
+        }
+    }
+
+    /*
+        The file the snapshot is kept in, named by the snapshot or else by its
+        request, as a position would change whenever requests are added or moved.
+        None when the snapshot is turned off.
+    */
+    pub fn file_stem(&self, request_name: Option<&str>) -> Result<Option<String>> {
+        if !self.enabled() {
+            return Ok(None);
+        }
+        let name = self
+            .name()
+            .or(request_name)
+            .filter(|name| !name.trim().is_empty())
+            .ok_or_else(|| anyhow!("A snapshot needs a name, either its own or the request's"))?;
+        Ok(Some(file_name(name)))
+    }
+
+    fn ignore(&self) -> &[String] {
+        match self {
+            Snapshot::Options { ignore, .. } => ignore,
+            _ => &[],
+        }
+    }
+}
+
+const IGNORED: &str = "<ignored>";
+
+// Lowercase, with anything that isn't a letter or number turned into `_`
+fn file_name(name: &str) -> String {
+    name.trim()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c.to_ascii_lowercase(),
+            false => '_',
+        })
+        .collect()
+}
+
+/*
+    Where a collection's snapshots live, and whether they're being rewritten.
+    Each `--data` row and `foreach` item gets its own snapshot, as their
+    responses are expected to differ.
+*/
+#[derive(Clone)]
+pub struct Snapshots {
+    dir: PathBuf,
+    update: bool,
+    iteration: Option<usize>,
+    item: Option<usize>,
+}
+
+impl Snapshots {
+    pub fn new(collection_dir: &Path, collection: &str, update: bool) -> Snapshots {
+        Snapshots {
+            dir: collection_dir
+                .join("_snapshots")
+                .join(file_name(collection)),
+            update,
+            iteration: None,
+            item: None,
+        }
+    }
+
+    pub fn for_iteration(&self, iteration: usize) -> Snapshots {
+        Snapshots {
+            iteration: Some(iteration),
+            ..self.clone()
+        }
+    }
+
+    pub fn for_item(&self, item: usize) -> Snapshots {
+        Snapshots {
+            item: Some(item),
+            ..self.clone()
+        }
+    }
+
+    // `.` never comes out of `file_name`, so these can't clash with another snapshot's name
+    fn file_stem(&self, name: String) -> String {
+        let mut stem = name;
+        if let Some(iteration) = self.iteration {
+            stem.push_str(&format!(".row_{}", iteration));
+        }
+        if let Some(item) = self.item {
+            stem.push_str(&format!(".item_{}", item));
+        }
+        stem
+    }
+
+    /*
+        Compares the response with its snapshot, storing it instead if there
+        isn't one yet.
+    */
+    pub fn check(
+        &self,
+        snapshot: &Snapshot,
+        request_name: Option<&str>,
+        response: &Response,
+    ) -> Result<Vec<String>> {
+        let Some(name) = snapshot.file_stem(request_name)? else {
+            return Ok(Vec::new());
+        };
+        let name = self.file_stem(name);
+
+        let text = response
+            .text()
+            .ok_or_else(|| anyhow!("Binary bodies can't be snapshotted"))?;
+        let json = serde_json::from_str::<Value>(&text).ok();
+        let (path, contents) = match &json {
+            Some(json) => (
+                self.dir.join(format!("{}.json", name)),
+                serde_json::to_string_pretty(json)? + "\n",
+            ),
+            None => (self.dir.join(format!("{}.txt", name)), text.clone()),
+        };
+
+        if self.update || !path.exists() {
+            fs::create_dir_all(&self.dir)
+                .with_context(|| format!("Failed to create {}", self.dir.display()))?;
+            fs::write(&path, contents)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            println!("Stored snapshot {}", path.display());
+            return Ok(Vec::new());
+        }
+
+        let stored = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        match json {
+            Some(json) => {
+                let stored = serde_json::from_str(&stored)
+                    .with_context(|| format!("Snapshot {} isn't valid JSON", path.display()))?;
+                let stored = mask(stored, snapshot.ignore())?;
+                let actual = mask(json, snapshot.ignore())?;
+                let mut differences = Vec::new();
+                diff("", &stored, &actual, &mut differences);
+                Ok(differences)
+            }
+            None if stored == text => Ok(Vec::new()),
+            None => Ok(vec!["/: body text differs".to_owned()]),
+        }
+    }
+}
+
+// Replaces every node an ignore path matches, so they compare as equal
+fn mask(mut value: Value, ignore: &[String]) -> Result<Value> {
+    for path in ignore {
+        for pointer in json::locate(path, &value)? {
+            if let Some(node) = value.pointer_mut(&pointer) {
+                *node = Value::from(IGNORED);
+            }
+        }
+    }
+    Ok(value)
+}
+
+fn escape(key: &str) -> String {
+    key.replace('~', "~0").replace('/', "~1")
+}
+
+// Every difference between the two, as `<JSON pointer>: <what changed>`
+fn diff(pointer: &str, expected: &Value, actual: &Value, out: &mut Vec<String>) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, value) in expected {
+                let pointer = format!("{}/{}", pointer, escape(key));
+                match actual.get(key) {
+                    Some(other) => diff(&pointer, value, other, out),
+                    None => out.push(format!("{}: missing, expected {}", pointer, value)),
+                }
+            }
+            for (key, value) in actual {
+                if !expected.contains_key(key) {
+                    out.push(format!("{}/{}: unexpected {}", pointer, escape(key), value));
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            for (idx, value) in expected.iter().enumerate() {
+                let pointer = format!("{}/{}", pointer, idx);
+                match actual.get(idx) {
+                    Some(other) => diff(&pointer, value, other, out),
+                    None => out.push(format!("{}: missing, expected {}", pointer, value)),
+                }
+            }
+            for (idx, value) in actual.iter().enumerate().skip(expected.len()) {
+                out.push(format!("{}/{}: unexpected {}", pointer, idx, value));
+            }
+        }
+        _ if expected != actual => {
+            let pointer = if pointer.is_empty() { "/" } else { pointer };
+            out.push(format!(
+                "{}: expected {}, got {}",
+                pointer, expected, actual
+            ));
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Snapshot, Snapshots};
+    use crate::common::scratch_dir;
+    use crate::response::ResponseBuilder;
+    use serde_json::json;
+    use std::fs;
+
+    fn response(body: serde_json::Value) -> crate::response::Response {
+        ResponseBuilder::new()
+            .body(body.to_string().into_bytes())
+            .build()
+    }
+
+    #[test]
+    fn store_then_compare() {
+        let dir = scratch_dir("snapshots");
+        let snapshots = Snapshots::new(&dir, "My Collection", false);
+        let snapshot: Snapshot =
+            serde_json::from_str(r#"{ "ignore": ["id", "items[*].at"] }"#).unwrap();
+
+        let first = response(json!({ "id": "a1", "items": [{ "n": 1, "at": 5 }], "ok": true }));
+        assert!(snapshots
+            .check(&snapshot, Some("Get Item"), &first)
+            .unwrap()
+            .is_empty());
+        let stored = dir.join("_snapshots/my_collection/get_item.json");
+        let contents = fs::read_to_string(&stored).unwrap();
+        assert!(contents.contains("\n  \"id\": \"a1\""), "{}", contents);
+
+        // Ignored fields can change freely
+        let second = response(json!({ "id": "b2", "items": [{ "n": 1, "at": 9 }], "ok": true }));
+        assert!(snapshots
+            .check(&snapshot, Some("Get Item"), &second)
+            .unwrap()
+            .is_empty());
+
+        let third = response(json!({ "id": "c3", "items": [{ "n": 2, "at": 9 }, 3], "new": 1 }));
+        let mut differences = snapshots
+            .check(&snapshot, Some("Get Item"), &third)
+            .unwrap();
+        differences.sort();
+        assert_eq!(
+            differences,
+            [
+                "/items/0/n: expected 1, got 2",
+                "/items/1: unexpected 3",
+                "/new: unexpected 1",
+                "/ok: missing, expected true",
+            ]
+        );
+
+        // Updating takes the new body as the snapshot
+        let updating = Snapshots::new(&dir, "My Collection", true);
+        assert!(updating
+            .check(&snapshot, Some("Get Item"), &third)
+            .unwrap()
+            .is_empty());
+        assert!(snapshots
+            .check(&snapshot, Some("Get Item"), &third)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn text_and_disabled() {
+        let dir = scratch_dir("snapshots_text");
+        let snapshots = Snapshots::new(&dir, "text", false);
+        let text = |body: &str| {
+            ResponseBuilder::new()
+                .body(body.as_bytes().to_vec())
+                .build()
+        };
+
+        let snapshot = Snapshot::Enabled(true);
+        assert!(snapshots
+            .check(&snapshot, Some("page"), &text("<p>hi</p>"))
+            .unwrap()
+            .is_empty());
+        assert!(dir.join("_snapshots/text/page.txt").exists());
+        assert!(snapshots
+            .check(&snapshot, Some("page"), &text("<p>hi</p>"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            snapshots
+                .check(&snapshot, Some("page"), &text("<p>bye</p>"))
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let disabled = Snapshot::Enabled(false);
+        assert!(snapshots
+            .check(&disabled, None, &text("x"))
+            .unwrap()
+            .is_empty());
+        assert!(!dir.join("_snapshots/text/other.txt").exists());
+    }
+
+    #[test]
+    fn rows_and_items() {
+        let dir = scratch_dir("snapshots_rows");
+        let snapshots = Snapshots::new(&dir, "rows", false);
+        let snapshot = Snapshot::Enabled(true);
+
+        let first = snapshots.for_iteration(1).for_item(0);
+        let second = snapshots.for_iteration(2).for_item(0);
+        let alice = response(json!({ "user": "alice" }));
+        let bob = response(json!({ "user": "bob" }));
+        assert!(first
+            .check(&snapshot, Some("me"), &alice)
+            .unwrap()
+            .is_empty());
+        assert!(second
+            .check(&snapshot, Some("me"), &bob)
+            .unwrap()
+            .is_empty());
+        assert!(dir.join("_snapshots/rows/me.row_1.item_0.json").exists());
+        assert!(dir.join("_snapshots/rows/me.row_2.item_0.json").exists());
+
+        assert!(first
+            .check(&snapshot, Some("me"), &alice)
+            .unwrap()
+            .is_empty());
+        assert!(!second
+            .check(&snapshot, Some("me"), &alice)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn names() {
+        let unnamed = Snapshot::Enabled(true);
+        assert!(unnamed.file_stem(None).is_err());
+        assert!(unnamed.file_stem(Some(" ")).is_err());
+        assert_eq!(
+            unnamed.file_stem(Some("Get Item")).unwrap().unwrap(),
+            "get_item"
+        );
+
+        let named: Snapshot = serde_json::from_str(r#"{ "name": "item-v2" }"#).unwrap();
+        assert_eq!(named.file_stem(None).unwrap().unwrap(), "item_v2");
+        assert_eq!(Snapshot::Enabled(false).file_stem(None).unwrap(), None);
+    }
+}